@@ -0,0 +1,57 @@
+use std::env;
+use std::path::Path;
+
+/// Decide whether an ffmpeg binary gets embedded.
+///
+/// With the `embedded-ffmpeg` feature the platform binary from `assets/` is embedded
+/// when there is one. Targets without a binary, or a checkout without the assets
+/// (e.g. the crates.io package), still build, and crunch then needs an external ffmpeg.
+fn main() {
+    println!("cargo::rustc-check-cfg=cfg(embedded_ffmpeg)");
+    println!("cargo::rerun-if-changed=build.rs");
+    // The directory rather than the asset, so a binary dropped in later is picked up
+    println!("cargo::rerun-if-changed=assets");
+
+    if env::var_os("CARGO_FEATURE_EMBEDDED_FFMPEG").is_none() {
+        return;
+    }
+
+    let target_os = env::var("CARGO_CFG_TARGET_OS").unwrap_or_default();
+    let asset = match target_os.as_str() {
+        "windows" => "assets/ffmpeg.exe",
+        "linux" => "assets/ffmpeg-linux",
+        "macos" => "assets/ffmpeg-macos",
+        _ => {
+            println!(
+                "cargo::warning=No embedded ffmpeg for {}, crunch will use an external ffmpeg",
+                target_os
+            );
+            return;
+        }
+    };
+
+    if !Path::new(asset).exists() {
+        println!(
+            "cargo::warning={} not found, crunch will use an external ffmpeg",
+            asset
+        );
+        return;
+    }
+
+    println!("cargo::rustc-cfg=embedded_ffmpeg");
+    // Hashed here rather than on every run, the binary is tens of megabytes
+    println!("cargo::rustc-env=CRUNCH_FFMPEG_SHA256={}", sha256(asset));
+}
+
+#[cfg(feature = "embedded-ffmpeg")]
+fn sha256(path: &str) -> String {
+    use sha2::{Digest, Sha256};
+
+    let bytes = std::fs::read(path).unwrap_or_else(|e| panic!("Failed to read {}: {}", path, e));
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+#[cfg(not(feature = "embedded-ffmpeg"))]
+fn sha256(_path: &str) -> String {
+    unreachable!("only called with the embedded-ffmpeg feature")
+}
@@ -25,11 +25,13 @@ pub const AUDIO_EXTENSIONS: &[&str] = &[
     "ra", "rm", "au", "gsm", "voc", "tta", "snd",
 ];
 
-#[cfg(target_os = "windows")]
+// Only present with the embedded-ffmpeg feature and the platform binary in assets/,
+// see build.rs
+#[cfg(all(embedded_ffmpeg, target_os = "windows"))]
 pub const FFMPEG_BINARY: &[u8] = include_bytes!("../assets/ffmpeg.exe");
 
-#[cfg(target_os = "linux")]
+#[cfg(all(embedded_ffmpeg, target_os = "linux"))]
 pub const FFMPEG_BINARY: &[u8] = include_bytes!("../assets/ffmpeg-linux");
 
-#[cfg(target_os = "macos")]
+#[cfg(all(embedded_ffmpeg, target_os = "macos"))]
 pub const FFMPEG_BINARY: &[u8] = include_bytes!("../assets/ffmpeg-macos");
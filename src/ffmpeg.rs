@@ -1,37 +1,39 @@
+mod animation;
+mod batch;
+mod cache;
+mod cancel;
+mod capabilities;
+mod codec;
 mod compress;
+#[cfg(embedded_ffmpeg)]
+mod embedded;
+mod image_metadata;
+mod locate;
+mod metadata;
+mod orientation;
+mod partial;
+mod preview;
+mod probe;
 mod progress_bar;
-
-use crate::consts::FFMPEG_BINARY;
-use anyhow::Result;
+mod responsive;
+mod runner;
+mod scale;
+mod target_size;
+#[cfg(test)]
+mod test_util;
+
+pub use animation::AnimatedOutput;
+pub use cache::{cache_dir, clean as clean_cache};
+pub use cancel::{CANCELLED_EXIT_CODE, install_handler as install_cancel_handler, is_cancelled};
+pub use capabilities::Capabilities;
 pub use compress::{
-    AudioCompressOptions, BaseCompressOptions, ImageCompressOptions, VideoCompressOptions,
-    compress_all_audios, compress_all_images, compress_all_videos,
+    AudioCompressOptions, BaseCompressOptions, ImageCompressOptions, NoGainAction, Outcome,
+    VideoCompressOptions, compress_all_audios, compress_all_images, compress_all_videos,
 };
-use std::fs;
-use std::io::Write;
-use std::path::PathBuf;
-use tempfile::tempdir;
-
-pub fn get_ffmpeg() -> Result<PathBuf> {
-    let temp_dir = tempdir()?;
-
-    #[cfg(target_os = "windows")]
-    let ffmpeg_path = temp_dir.path().join("ffmpeg.exe");
-
-    #[cfg(not(target_os = "windows"))]
-    let ffmpeg_path = temp_dir.path().join("ffmpeg");
-
-    let mut file = fs::File::create(&ffmpeg_path)?;
-    file.write_all(FFMPEG_BINARY)?;
-
-    #[cfg(unix)]
-    {
-        use std::os::unix::fs::PermissionsExt;
-        fs::set_permissions(&ffmpeg_path, fs::Permissions::from_mode(0o755))?;
-    }
-
-    // Leak tempdir so it doesn't get deleted
-    Box::leak(Box::new(temp_dir));
-
-    Ok(ffmpeg_path)
-}
+pub use locate::get_ffmpeg;
+pub use metadata::MetadataPolicy;
+pub use preview::{PosterAt, PreviewFormat, PreviewOptions, extract_all_previews};
+pub use probe::MediaInfo;
+pub use responsive::{Responsive, SrcsetFormat};
+pub use scale::Resize;
+pub use target_size::TargetSize;
@@ -0,0 +1,278 @@
+use super::codec::VideoProfile;
+use anyhow::Result;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// The image formats that can hold an animation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnimatedFormat {
+    Gif,
+    /// APNG, a png with an "acTL" chunk
+    Png,
+    WebP,
+}
+
+/// An animated image input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Animation {
+    pub format: AnimatedFormat,
+    /// How many times the animation plays, 0 = forever
+    pub plays: u32,
+}
+
+/// What `--animated` turns animated inputs into.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum AnimatedOutput {
+    /// An animation in the `--images` format, with the same loop count. Formats that
+    /// can't animate (jpg) get the first frame.
+    #[default]
+    Image,
+    /// A muted mp4 or webm video, to be played with `<video autoplay loop muted>`
+    Video(VideoProfile),
+}
+
+impl AnimatedOutput {
+    /// "image", or the video format to write, e.g. "mp4".
+    pub fn for_format(format: &str) -> Result<Self> {
+        match format {
+            "image" => Ok(Self::Image),
+            video => Ok(Self::Video(VideoProfile::for_format(video)?)),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Image => "image",
+            Self::Video(profile) => profile.container,
+        }
+    }
+}
+
+impl Animation {
+    /// The animation of the GIF, APNG or WebP at `path`, or `None` when it's a still
+    /// (or not one of those formats at all).
+    pub fn read(path: &Path) -> Option<Self> {
+        let mut file = File::open(path).ok()?;
+        let mut magic = [0u8; 12];
+        file.read_exact(&mut magic).ok()?;
+
+        // Only image files are read whole
+        let is_gif = magic.starts_with(b"GIF8");
+        let is_png = magic.starts_with(b"\x89PNG\r\n\x1a\n");
+        let is_webp = magic.starts_with(b"RIFF") && &magic[8..12] == b"WEBP";
+        if !(is_gif || is_png || is_webp) {
+            return None;
+        }
+
+        let mut data = magic.to_vec();
+        file.read_to_end(&mut data).ok()?;
+
+        if is_gif {
+            parse_gif(&data)
+        } else if is_png {
+            parse_apng(&data)
+        } else {
+            parse_webp(&data)
+        }
+    }
+}
+
+/// Walk the GIF blocks until a second frame shows up. The loop count is in the
+/// "NETSCAPE2.0" application extension, which comes before the frames.
+fn parse_gif(data: &[u8]) -> Option<Animation> {
+    // Header and logical screen descriptor, then the global color table if there is one
+    let flags = *data.get(10)?;
+    let mut pos = 13;
+    if flags & 0x80 != 0 {
+        pos += 3 << ((flags & 0x07) + 1);
+    }
+
+    // Without the extension a GIF plays once
+    let mut plays = 1;
+    let mut frames = 0;
+    loop {
+        match *data.get(pos)? {
+            // Extension: label, then sub-blocks
+            0x21 => {
+                let label = *data.get(pos + 1)?;
+                if label == 0xFF && data.get(pos + 3..pos + 14) == Some(b"NETSCAPE2.0") {
+                    let loops = u16::from_le_bytes([*data.get(pos + 16)?, *data.get(pos + 17)?]);
+                    // The count is of repeats after the first play, 0 = forever
+                    plays = if loops == 0 { 0 } else { loops as u32 + 1 };
+                }
+                pos = skip_sub_blocks(data, pos + 2)?;
+            }
+            // Image descriptor, an optional local color table, then the LZW data
+            0x2C => {
+                frames += 1;
+                if frames > 1 {
+                    return Some(Animation {
+                        format: AnimatedFormat::Gif,
+                        plays,
+                    });
+                }
+                let flags = *data.get(pos + 9)?;
+                pos += 10;
+                if flags & 0x80 != 0 {
+                    pos += 3 << ((flags & 0x07) + 1);
+                }
+                pos = skip_sub_blocks(data, pos + 1)?;
+            }
+            // Trailer
+            _ => return None,
+        }
+    }
+}
+
+/// Skip the size-prefixed sub-blocks starting at `pos`, up to and including the empty one.
+fn skip_sub_blocks(data: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        let size = *data.get(pos)? as usize;
+        pos += 1 + size;
+        if size == 0 {
+            return Some(pos);
+        }
+    }
+}
+
+/// An APNG has an "acTL" chunk before its image data.
+fn parse_apng(data: &[u8]) -> Option<Animation> {
+    let mut pos = 8;
+    while let Some(header) = data.get(pos..pos + 8) {
+        let length = u32::from_be_bytes(header[..4].try_into().ok()?) as usize;
+        let chunk = &header[4..];
+        if chunk == b"IDAT" {
+            return None;
+        }
+        if chunk == b"acTL" {
+            let body = data.get(pos + 8..pos + 16)?;
+            let frames = u32::from_be_bytes(body[..4].try_into().ok()?);
+            let plays = u32::from_be_bytes(body[4..].try_into().ok()?);
+            return (frames > 1).then_some(Animation {
+                format: AnimatedFormat::Png,
+                plays,
+            });
+        }
+        // Length, type, data, crc
+        pos += 12 + length;
+    }
+
+    None
+}
+
+/// An animated WebP sets the animation flag of its "VP8X" chunk, and has its loop
+/// count in the "ANIM" chunk.
+fn parse_webp(data: &[u8]) -> Option<Animation> {
+    let mut pos = 12;
+    let mut animated = false;
+    while let Some(header) = data.get(pos..pos + 8) {
+        let size = u32::from_le_bytes(header[4..].try_into().ok()?) as usize;
+        let body = data.get(pos + 8..pos + 8 + size)?;
+        match &header[..4] {
+            b"VP8X" => animated = body.first()? & 0x02 != 0,
+            b"ANIM" if animated => {
+                let plays = u16::from_le_bytes(body.get(4..6)?.try_into().ok()?);
+                return Some(Animation {
+                    format: AnimatedFormat::WebP,
+                    plays: plays as u32,
+                });
+            }
+            _ => {}
+        }
+        pos += webp_chunk_size(size);
+    }
+
+    None
+}
+
+/// The full size of a WebP chunk with `size` bytes of data: the header, the data and the
+/// padding byte an odd size is followed by.
+pub fn webp_chunk_size(size: usize) -> usize {
+    8 + size + size % 2
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ffmpeg::test_util::png_chunk;
+
+    /// A 1x1 GIF with `frames` frames and, when given, a NETSCAPE loop count.
+    fn gif(frames: usize, loops: Option<u16>) -> Vec<u8> {
+        let mut gif = b"GIF89a\x01\x00\x01\x00\x80\x00\x00".to_vec();
+        gif.extend([0, 0, 0, 255, 255, 255]);
+        if let Some(loops) = loops {
+            gif.extend(b"\x21\xFF\x0BNETSCAPE2.0\x03\x01");
+            gif.extend(loops.to_le_bytes());
+            gif.push(0);
+        }
+        for _ in 0..frames {
+            // Graphic control extension, then the frame
+            gif.extend(b"\x21\xF9\x04\x00\x0A\x00\x00\x00");
+            gif.extend(b"\x2C\x00\x00\x00\x00\x01\x00\x01\x00\x00");
+            gif.extend(b"\x02\x02\x44\x01\x00");
+        }
+        gif.push(0x3B);
+        gif
+    }
+
+    fn webp_chunk(kind: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut chunk = kind.to_vec();
+        chunk.extend((body.len() as u32).to_le_bytes());
+        chunk.extend(body);
+        chunk
+    }
+
+    #[test]
+    fn test_gif() {
+        assert_eq!(
+            parse_gif(&gif(3, Some(0))),
+            Some(Animation {
+                format: AnimatedFormat::Gif,
+                plays: 0
+            })
+        );
+        // Two repeats after the first play
+        assert_eq!(parse_gif(&gif(2, Some(2))).unwrap().plays, 3);
+        assert_eq!(parse_gif(&gif(2, None)).unwrap().plays, 1);
+        assert_eq!(parse_gif(&gif(1, Some(0))), None);
+    }
+
+    #[test]
+    fn test_apng_and_webp() {
+        let mut apng = b"\x89PNG\r\n\x1a\n".to_vec();
+        apng.extend(png_chunk(b"IHDR", &[0; 13]));
+        let mut actl = 4u32.to_be_bytes().to_vec();
+        actl.extend(2u32.to_be_bytes());
+        apng.extend(png_chunk(b"acTL", &actl));
+        apng.extend(png_chunk(b"IDAT", &[0; 4]));
+        assert_eq!(
+            parse_apng(&apng),
+            Some(Animation {
+                format: AnimatedFormat::Png,
+                plays: 2
+            })
+        );
+
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        png.extend(png_chunk(b"IHDR", &[0; 13]));
+        png.extend(png_chunk(b"IDAT", &[0; 4]));
+        assert_eq!(parse_apng(&png), None);
+
+        let mut webp = b"RIFF\0\0\0\0WEBP".to_vec();
+        webp.extend(webp_chunk(b"VP8X", &[0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0]));
+        webp.extend(webp_chunk(b"ANIM", &[0, 0, 0, 0, 5, 0]));
+        assert_eq!(
+            parse_webp(&webp),
+            Some(Animation {
+                format: AnimatedFormat::WebP,
+                plays: 5
+            })
+        );
+
+        let mut still = b"RIFF\0\0\0\0WEBP".to_vec();
+        still.extend(webp_chunk(b"VP8X", &[0; 10]));
+        still.extend(webp_chunk(b"VP8 ", &[0; 10]));
+        assert_eq!(parse_webp(&still), None);
+    }
+}
@@ -0,0 +1,149 @@
+use super::cancel;
+use super::compress::{BaseCompressOptions, Outcome};
+use super::probe::{MediaInfo, probe_media};
+use super::progress_bar::{init_file_bar, init_progress_bar};
+use crate::summary::{FileRecord, MediaKind};
+use anyhow::{Result, anyhow};
+use indicatif::{MultiProgress, ParallelProgressIterator, ProgressBar};
+use rayon::prelude::*;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// What the `compress_all_*` functions share with the batch loop.
+pub struct Batch<'a> {
+    pub ffmpeg: &'a Path,
+    pub kind: MediaKind,
+    /// The formats every file is compressed to, one record each
+    pub targets: Vec<Target<'a>>,
+    /// Show a child bar per in-flight file, for media whose encodes take a while
+    pub file_bars: bool,
+    pub finish_message: &'static str,
+}
+
+/// One output format of a batch.
+pub struct Target<'a> {
+    pub base: &'a BaseCompressOptions,
+    /// ffmpeg encoder and settings, recorded for every file
+    pub codec: &'a str,
+    pub settings: BTreeMap<String, String>,
+}
+
+/// Run `compress` over `files` in parallel, with an overall progress bar on top and
+/// (for `file_bars`) one child bar per in-flight file below it.
+///
+/// Each file is probed first, so `compress` gets its [`MediaInfo`], along with the
+/// indices of the targets still to do. It returns one [`Outcome`] per index, an error
+/// fails them all. Every file and target ends up as a [`FileRecord`], and in the
+/// manifest when there is one.
+pub fn run_batch<F>(files: &[PathBuf], batch: Batch, compress: F) -> Vec<FileRecord>
+where
+    F: Fn(&Path, &MediaInfo, &[usize], &ProgressBar) -> Result<Vec<Outcome>> + Sync,
+{
+    let mp = MultiProgress::new();
+    let pb = mp.add(init_progress_bar(files.len() as u64));
+    let several = batch.targets.len() > 1;
+
+    let results: Vec<Vec<FileRecord>> = files
+        .par_iter()
+        .map(|file| {
+            // Note: Avoid using println! here as it interferes with the progress bar
+            let name = file.file_name().unwrap_or_default().to_string_lossy();
+            pb.println(format!("Processing: {}", name));
+
+            let bar = if batch.file_bars {
+                mp.add(init_file_bar(&name))
+            } else {
+                ProgressBar::hidden()
+            };
+
+            let start = Instant::now();
+            let cancelled = cancel::is_cancelled();
+            let resumed: Vec<bool> = batch
+                .targets
+                .iter()
+                .map(|target| {
+                    !cancelled
+                        && target.base.manifest.as_ref().is_some_and(|manifest| {
+                            manifest.is_done(file, target.codec, &target.settings)
+                        })
+                })
+                .collect();
+            let pending: Vec<usize> = (0..resumed.len()).filter(|&i| !resumed[i]).collect();
+
+            // After Ctrl-C the remaining files are run through without starting ffmpeg
+            let mut media = None;
+            let res = if cancelled || pending.is_empty() {
+                Ok(Vec::new())
+            } else {
+                let info = media.insert(probe_media(batch.ffmpeg, file));
+                compress(file, info, &pending, &bar)
+            };
+
+            // An encode killed by the cancellation isn't a failure, and is left for --resume
+            let cancelled = cancelled || (res.is_err() && cancel::is_cancelled());
+            let duration = start.elapsed();
+
+            bar.finish_and_clear();
+            mp.remove(&bar);
+
+            let outcomes: Vec<Result<Outcome>> = match res {
+                Ok(outcomes) => outcomes.into_iter().map(Ok).collect(),
+                Err(e) => pending.iter().map(|_| Err(anyhow!("{}", e))).collect(),
+            };
+            let mut outcomes = outcomes.into_iter();
+
+            let mut records = Vec::new();
+            for (target, resumed) in batch.targets.iter().zip(resumed) {
+                let res = if cancelled {
+                    Ok(Outcome::Skipped("cancelled".to_string()))
+                } else if resumed {
+                    Ok(Outcome::Skipped("done in a previous run".to_string()))
+                } else {
+                    outcomes
+                        .next()
+                        .unwrap_or_else(|| Err(anyhow!("No result for {}", target.codec)))
+                };
+
+                let label = if several {
+                    format!("{} [{}]", name, target.codec)
+                } else {
+                    name.to_string()
+                };
+                match &res {
+                    Ok(Outcome::Skipped(reason)) => {
+                        pb.println(format!("Skipped: {} ({})", label, reason))
+                    }
+                    Ok(_) => pb.println(format!("Finished: {} (took {:.1?})", label, duration)),
+                    Err(e) => {
+                        // Print the actual error to the console so you can see it
+                        pb.println(format!("FAILED: {} \nReason: {}", label, e));
+                    }
+                }
+
+                let record = FileRecord::new(batch.kind, file, &res, duration)
+                    .with_encoder(target.codec, target.settings.clone())
+                    .with_media(media.clone());
+
+                // Resumed targets keep the entry of the run that compressed them, and
+                // cancelled ones keep their "started" entry
+                if let Some(manifest) = &target.base.manifest
+                    && !resumed
+                    && !cancelled
+                    && let Err(e) = manifest.finish(&record)
+                {
+                    pb.println(format!("WARNING: {}", e));
+                }
+
+                records.push(record);
+            }
+
+            records
+        })
+        .progress_with(pb.clone()) // Attach the progress bar to rayon iterator
+        .collect();
+
+    pb.finish_with_message(batch.finish_message);
+
+    results.into_iter().flatten().collect()
+}
@@ -0,0 +1,34 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The per-user directory for the extracted embedded ffmpeg and the probed ffmpeg
+/// capabilities, e.g. `~/.cache/crunch`.
+pub fn cache_dir() -> Result<PathBuf> {
+    let base = dirs::cache_dir().context("Could not find a cache directory for this user")?;
+    Ok(base.join("crunch"))
+}
+
+/// Remove the cache directory. Returns the number of bytes freed.
+pub fn clean() -> Result<u64> {
+    let dir = cache_dir()?;
+    if !dir.exists() {
+        return Ok(0);
+    }
+
+    let size = dir_size(&dir);
+    fs::remove_dir_all(&dir)
+        .with_context(|| format!("Failed to remove cache directory: {}", dir.display()))?;
+
+    Ok(size)
+}
+
+fn dir_size(dir: &Path) -> u64 {
+    walkdir::WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.metadata().ok())
+        .filter(|meta| meta.is_file())
+        .map(|meta| meta.len())
+        .sum()
+}
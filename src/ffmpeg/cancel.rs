@@ -0,0 +1,69 @@
+use anyhow::{Context, Result};
+use std::process;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Exit code for a run stopped by Ctrl-C, as shells report SIGINT.
+pub const CANCELLED_EXIT_CODE: u8 = 130;
+
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+
+/// Pids of the ffmpeg processes currently running.
+static CHILDREN: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+/// Handle Ctrl-C (SIGINT) and SIGTERM: stop starting new files, terminate the running
+/// ffmpeg processes and let the batches wind down, so partial outputs get cleaned up
+/// and a partial summary is printed. A second Ctrl-C exits immediately.
+pub fn install_handler() -> Result<()> {
+    ctrlc::set_handler(|| {
+        if CANCELLED.swap(true, Ordering::SeqCst) {
+            process::exit(CANCELLED_EXIT_CODE as i32);
+        }
+
+        eprintln!("\nCancelling, waiting for ffmpeg to stop... (press Ctrl-C again to force)");
+        for pid in CHILDREN.lock().unwrap().iter() {
+            terminate(*pid);
+        }
+    })
+    .context("Failed to install Ctrl-C handler")
+}
+
+pub fn is_cancelled() -> bool {
+    CANCELLED.load(Ordering::SeqCst)
+}
+
+/// Tracks a running ffmpeg process until it is dropped.
+pub struct ChildGuard(u32);
+
+impl ChildGuard {
+    pub fn register(pid: u32) -> Self {
+        CHILDREN.lock().unwrap().push(pid);
+
+        // Spawned just after the handler ran, it would otherwise never be told to stop
+        if is_cancelled() {
+            terminate(pid);
+        }
+
+        Self(pid)
+    }
+}
+
+impl Drop for ChildGuard {
+    fn drop(&mut self) {
+        CHILDREN.lock().unwrap().retain(|pid| *pid != self.0);
+    }
+}
+
+#[cfg(unix)]
+fn terminate(pid: u32) {
+    // SAFETY: kill has no memory safety requirements, a stale pid just fails with ESRCH
+    unsafe {
+        libc::kill(pid as libc::pid_t, libc::SIGTERM);
+    }
+}
+
+#[cfg(not(unix))]
+fn terminate(_pid: u32) {
+    // On Windows the console delivers Ctrl-C to every process attached to it,
+    // ffmpeg included, so there is nothing to forward.
+}
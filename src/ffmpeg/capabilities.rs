@@ -0,0 +1,342 @@
+use super::cache::cache_dir;
+use super::codec::{
+    AUDIO_FORMATS, AudioCodec, IMAGE_FORMATS, ImageEncoder, VIDEO_FORMATS, VideoProfile,
+    muxer_for_extension,
+};
+use super::runner::run_ffmpeg;
+use crate::summary::MediaKind;
+use anyhow::{Result, bail};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Name of the probe cache, in the cache directory.
+const CACHE_FILE: &str = "capabilities.json";
+
+/// What the ffmpeg binary in use reports about itself.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Capabilities {
+    /// e.g. "6.1.1" or "N-113348-g0a5813fc68"
+    pub version: Option<String>,
+    encoders: BTreeSet<String>,
+    muxers: BTreeSet<String>,
+}
+
+/// A probe result, valid as long as the binary's size and mtime don't change.
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheEntry {
+    size: u64,
+    modified: u128,
+    capabilities: Capabilities,
+}
+
+impl Capabilities {
+    /// The capabilities of `ffmpeg`, probed on first use and cached per binary afterwards.
+    pub fn load(ffmpeg: &Path) -> Result<Self> {
+        let cache_file = cache_dir()?.join(CACHE_FILE);
+        let mut cache: BTreeMap<PathBuf, CacheEntry> = fs::read_to_string(&cache_file)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+
+        let key = fs::canonicalize(ffmpeg).unwrap_or_else(|_| ffmpeg.to_path_buf());
+        let (size, modified) = fingerprint(&key);
+
+        if let Some(entry) = cache.get(&key)
+            && entry.size == size
+            && entry.modified == modified
+        {
+            return Ok(entry.capabilities.clone());
+        }
+
+        let capabilities = Self::probe(ffmpeg)?;
+        cache.insert(
+            key,
+            CacheEntry {
+                size,
+                modified,
+                capabilities: capabilities.clone(),
+            },
+        );
+
+        // Only a speed-up, a cache that can't be written just means probing again next time
+        if let Ok(content) = serde_json::to_vec_pretty(&cache) {
+            let tmp = cache_file.with_extension("json.tmp");
+            let _ = fs::create_dir_all(cache_file.parent().unwrap_or(Path::new(".")))
+                .and_then(|_| fs::write(&tmp, content))
+                .and_then(|_| fs::rename(&tmp, &cache_file));
+        }
+
+        Ok(capabilities)
+    }
+
+    /// Ask ffmpeg for its version and the encoders and muxers it was built with.
+    pub fn probe(ffmpeg: &Path) -> Result<Self> {
+        let version = run_ffmpeg(
+            ffmpeg,
+            &["-hide_banner".to_string(), "-version".to_string()],
+        )?;
+
+        let mut listings = Vec::new();
+        for listing in ["-encoders", "-muxers"] {
+            let output = run_ffmpeg(ffmpeg, &["-hide_banner".to_string(), listing.to_string()])?;
+            if !output.status.success() {
+                bail!(
+                    "{} {} failed: {}",
+                    ffmpeg.display(),
+                    listing,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                );
+            }
+            listings.push(parse_listing(&String::from_utf8_lossy(&output.stdout)));
+        }
+        let muxers = listings.pop().unwrap_or_default();
+        let encoders = listings.pop().unwrap_or_default();
+
+        Ok(Self {
+            version: parse_version(&String::from_utf8_lossy(&version.stdout)),
+            encoders,
+            muxers,
+        })
+    }
+
+    pub fn has_encoder(&self, name: &str) -> bool {
+        self.encoders.contains(name)
+    }
+
+    pub fn has_muxer(&self, name: &str) -> bool {
+        self.muxers.contains(name)
+    }
+
+    /// What this ffmpeg lacks to write `kind` files in `format`, e.g. "libx265 encoder".
+    /// Errors for formats crunch doesn't know at all.
+    pub fn missing(&self, kind: MediaKind, format: &str) -> Result<Vec<String>> {
+        let (encoders, extension) = match kind {
+            // GIF is only ever written as a preview
+            MediaKind::Preview if format.eq_ignore_ascii_case("gif") => (vec!["gif"], format),
+            MediaKind::Image | MediaKind::Preview => (
+                vec![ImageEncoder::for_format(format, "medium")?.codec()],
+                format,
+            ),
+            MediaKind::Video => {
+                let profile = VideoProfile::for_format(format)?;
+                (
+                    vec![profile.codec.encoder(), profile.audio_codec],
+                    profile.container,
+                )
+            }
+            MediaKind::Audio => (vec![AudioCodec::for_format(format)?.encoder()], format),
+        };
+
+        let mut missing: Vec<String> = encoders
+            .into_iter()
+            .filter(|encoder| !self.has_encoder(encoder))
+            .map(|encoder| format!("{} encoder", encoder))
+            .collect();
+
+        if let Some(muxer) = muxer_for_extension(extension)
+            && !self.has_muxer(muxer)
+        {
+            missing.push(format!("{} muxer", muxer));
+        }
+
+        Ok(missing)
+    }
+
+    /// The formats of `kind` this ffmpeg can write.
+    pub fn supported_formats(&self, kind: MediaKind) -> Vec<&'static str> {
+        let formats = match kind {
+            MediaKind::Image | MediaKind::Preview => IMAGE_FORMATS,
+            MediaKind::Video => VIDEO_FORMATS,
+            MediaKind::Audio => AUDIO_FORMATS,
+        };
+
+        formats
+            .iter()
+            .copied()
+            .filter(|format| self.missing(kind, format).is_ok_and(|m| m.is_empty()))
+            .collect()
+    }
+
+    /// Fail with a readable message when `kind` files can't be compressed to `format`.
+    pub fn check(&self, kind: MediaKind, format: &str) -> Result<()> {
+        let missing = self.missing(kind, format)?;
+        if missing.is_empty() {
+            return Ok(());
+        }
+
+        let supported = self.supported_formats(kind);
+        bail!(
+            "This ffmpeg can't compress {}s to {}, it has no {}.\n\
+             {} formats it supports: {}\n\
+             Use --ffmpeg to pick a different ffmpeg build.",
+            kind.as_str(),
+            format,
+            missing.join(" or "),
+            capitalize(kind.as_str()),
+            if supported.is_empty() {
+                "none".to_string()
+            } else {
+                supported.join(", ")
+            }
+        )
+    }
+}
+
+fn fingerprint(path: &Path) -> (u64, u128) {
+    let Ok(meta) = fs::metadata(path) else {
+        return (0, 0);
+    };
+    let modified = meta
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|since| since.as_nanos())
+        .unwrap_or_default();
+
+    (meta.len(), modified)
+}
+
+fn capitalize(s: &str) -> String {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// Pull the version out of "ffmpeg version 6.1.1 Copyright (c) 2000-2023 ...".
+fn parse_version(stdout: &str) -> Option<String> {
+    let line = stdout.lines().next()?;
+    let version = line
+        .strip_prefix("ffmpeg version ")?
+        .split_whitespace()
+        .next()?;
+
+    Some(version.to_string())
+}
+
+/// Parse an `-encoders` or `-muxers` listing: a legend, a line of dashes, then one
+/// " V....D libx264   description" or "  E webm   description" line per entry.
+fn parse_listing(stdout: &str) -> BTreeSet<String> {
+    stdout
+        .lines()
+        .skip_while(|line| {
+            let line = line.trim();
+            line.is_empty() || !line.chars().all(|c| c == '-')
+        })
+        .skip(1)
+        .filter_map(|line| line.split_whitespace().nth(1))
+        // Some entries list aliases, e.g. "mov,mp4,m4a"
+        .flat_map(|names| names.split(','))
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn capabilities() -> Capabilities {
+        let encoders = "\
+Encoders:
+ V..... = Video
+ A..... = Audio
+ ------
+ V....D libx264              libx264 H.264 / AVC / MPEG-4 AVC (codec h264)
+ V....D libwebp              libwebp WebP image (codec webp)
+ V....D mjpeg                MJPEG (Motion JPEG)
+ A....D aac                  AAC (Advanced Audio Coding)
+ A....D libopus              libopus Opus (codec opus)
+";
+        let muxers = "\
+File formats:
+ D. = Demuxing supported
+ .E = Muxing supported
+ --
+  E image2          image2 sequence
+  E mp4             MP4 (MPEG-4 Part 14)
+  E webm            WebM
+  E webp            WebP
+";
+
+        Capabilities {
+            version: None,
+            encoders: parse_listing(encoders),
+            muxers: parse_listing(muxers),
+        }
+    }
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(
+            parse_version(
+                "ffmpeg version 6.1.1 Copyright (c) 2000-2023 the FFmpeg developers\nbuilt with gcc"
+            ),
+            Some("6.1.1".to_string())
+        );
+        assert_eq!(
+            parse_version("ffmpeg version N-113348-g0a5813fc68-20240130 Copyright"),
+            Some("N-113348-g0a5813fc68-20240130".to_string())
+        );
+        assert_eq!(parse_version("bash: ffmpeg: command not found"), None);
+    }
+
+    #[test]
+    fn test_parse_listing() {
+        let capabilities = capabilities();
+
+        assert!(capabilities.has_encoder("libx264"));
+        assert!(capabilities.has_encoder("aac"));
+        assert!(capabilities.has_muxer("webm"));
+        // Legend lines aren't entries
+        assert!(!capabilities.has_encoder("="));
+        assert!(!capabilities.has_muxer("="));
+    }
+
+    #[test]
+    fn test_missing_and_supported_formats() {
+        let capabilities = capabilities();
+
+        assert!(
+            capabilities
+                .missing(MediaKind::Video, "mp4")
+                .unwrap()
+                .is_empty()
+        );
+        // webm needs libvpx-vp9, the webm muxer alone isn't enough
+        assert_eq!(
+            capabilities.missing(MediaKind::Video, "webm").unwrap(),
+            vec!["libvpx-vp9 encoder"]
+        );
+        assert_eq!(
+            capabilities.missing(MediaKind::Video, "mkv").unwrap(),
+            vec!["libx265 encoder", "matroska muxer"]
+        );
+        assert!(capabilities.missing(MediaKind::Video, "gif").is_err());
+        assert!(capabilities.missing(MediaKind::Image, "gif").is_err());
+        assert_eq!(
+            capabilities.missing(MediaKind::Preview, "gif").unwrap(),
+            vec!["gif encoder", "gif muxer"]
+        );
+
+        assert_eq!(
+            capabilities.supported_formats(MediaKind::Image),
+            vec!["webp", "jpg", "jpeg"]
+        );
+        assert_eq!(
+            capabilities.supported_formats(MediaKind::Video),
+            vec!["mp4"]
+        );
+
+        assert!(capabilities.check(MediaKind::Image, "webp").is_ok());
+        let error = capabilities
+            .check(MediaKind::Video, "av1")
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("no libsvtav1 encoder"));
+        assert!(error.contains("Video formats it supports: mp4"));
+    }
+}
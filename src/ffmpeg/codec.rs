@@ -0,0 +1,755 @@
+use anyhow::{Result, bail};
+use std::collections::BTreeMap;
+
+/// Image output formats crunch knows how to encode.
+pub const IMAGE_FORMATS: &[&str] = &["webp", "avif", "jpg", "jpeg", "png"];
+
+/// The ffmpeg encoder and its settings for a single image target format.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImageEncoder {
+    /// libwebp, quality: 0-100 (higher = better), compression_level: 0-6 (higher = more effort)
+    WebP { quality: u8, compression_level: u8 },
+    /// libaom-av1 in still-picture mode, crf: 0-63 (lower = better)
+    Avif { crf: u8, cpu_used: u8 },
+    /// mjpeg, qscale: 2-31 (lower = better)
+    Jpeg { qscale: u8 },
+    /// png is lossless, compression_level: 0-9 (higher = smaller, slower)
+    Png { compression_level: u8 },
+}
+
+impl ImageEncoder {
+    /// Pick the encoder for `extension` and map `level` onto its quality scale.
+    pub fn for_format(extension: &str, level: &str) -> Result<Self> {
+        let encoder = match extension.to_lowercase().as_str() {
+            "webp" => {
+                let (quality, compression_level) = match level {
+                    "low" => (90, 4),    // Low compression = high quality
+                    "medium" => (75, 5), // Balanced
+                    "high" => (50, 6),   // High compression = smaller size
+                    _ => (75, 5),        // Default to medium
+                };
+                Self::WebP {
+                    quality,
+                    compression_level,
+                }
+            }
+            "avif" => {
+                let crf = match level {
+                    "low" => 20,
+                    "medium" => 30,
+                    "high" => 40,
+                    _ => 30,
+                };
+                Self::Avif { crf, cpu_used: 6 }
+            }
+            "jpg" | "jpeg" => {
+                let qscale = match level {
+                    "low" => 2,
+                    "medium" => 5,
+                    "high" => 10,
+                    _ => 5,
+                };
+                Self::Jpeg { qscale }
+            }
+            "png" => {
+                let compression_level = match level {
+                    "low" => 6,
+                    "medium" => 8,
+                    "high" => 9,
+                    _ => 8,
+                };
+                Self::Png { compression_level }
+            }
+            other => bail!(
+                "Unsupported image format '{}'. Supported: {}",
+                other,
+                IMAGE_FORMATS.join(", ")
+            ),
+        };
+
+        Ok(encoder)
+    }
+
+    /// Name of the ffmpeg encoder, as passed to `-c:v`.
+    pub fn codec(&self) -> &'static str {
+        match self {
+            Self::WebP { .. } => "libwebp",
+            Self::Avif { .. } => "libaom-av1",
+            Self::Jpeg { .. } => "mjpeg",
+            Self::Png { .. } => "png",
+        }
+    }
+
+    /// The encoder settings as name/value pairs, for reports.
+    pub fn settings(&self) -> BTreeMap<String, String> {
+        let settings = match self {
+            Self::WebP {
+                quality,
+                compression_level,
+            } => vec![
+                ("quality", quality.to_string()),
+                ("compression_level", compression_level.to_string()),
+            ],
+            Self::Avif { crf, cpu_used } => {
+                vec![("crf", crf.to_string()), ("cpu_used", cpu_used.to_string())]
+            }
+            Self::Jpeg { qscale } => vec![("qscale", qscale.to_string())],
+            Self::Png { compression_level } => {
+                vec![("compression_level", compression_level.to_string())]
+            }
+        };
+
+        settings
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value))
+            .collect()
+    }
+
+    /// Encoder arguments, starting with `-c:v`.
+    pub fn args(&self) -> Vec<String> {
+        let mut args = vec!["-c:v".to_string(), self.codec().to_string()];
+
+        match self {
+            Self::WebP {
+                quality,
+                compression_level,
+            } => args.extend([
+                "-quality".to_string(),
+                quality.to_string(),
+                "-compression_level".to_string(),
+                compression_level.to_string(),
+            ]),
+            Self::Avif { crf, cpu_used } => args.extend([
+                "-still-picture".to_string(),
+                "1".to_string(),
+                // libaom needs -b:v 0 for constant quality mode
+                "-b:v".to_string(),
+                "0".to_string(),
+                "-crf".to_string(),
+                crf.to_string(),
+                "-cpu-used".to_string(),
+                cpu_used.to_string(),
+                "-frames:v".to_string(),
+                "1".to_string(),
+            ]),
+            Self::Jpeg { qscale } => args.extend([
+                "-q:v".to_string(),
+                qscale.to_string(),
+                "-frames:v".to_string(),
+                "1".to_string(),
+            ]),
+            Self::Png { compression_level } => args.extend([
+                "-pred".to_string(),
+                "mixed".to_string(),
+                "-compression_level".to_string(),
+                compression_level.to_string(),
+                "-frames:v".to_string(),
+                "1".to_string(),
+            ]),
+        }
+
+        args
+    }
+
+    /// Encoder and muxer arguments that keep an animated input animated, playing `plays`
+    /// times (0 = forever). `None` for formats that can't animate.
+    pub fn animated_args(&self, plays: u32) -> Option<Vec<String>> {
+        let args = match self {
+            Self::WebP {
+                quality,
+                compression_level,
+            } => vec![
+                "-c:v".to_string(),
+                "libwebp".to_string(),
+                "-quality".to_string(),
+                quality.to_string(),
+                "-compression_level".to_string(),
+                compression_level.to_string(),
+                "-loop".to_string(),
+                plays.to_string(),
+            ],
+            Self::Avif { crf, cpu_used } => vec![
+                "-c:v".to_string(),
+                "libaom-av1".to_string(),
+                "-b:v".to_string(),
+                "0".to_string(),
+                "-crf".to_string(),
+                crf.to_string(),
+                "-cpu-used".to_string(),
+                cpu_used.to_string(),
+                "-loop".to_string(),
+                plays.to_string(),
+            ],
+            // The png encoder only writes stills, APNG has an encoder and muxer of its own
+            Self::Png { compression_level } => vec![
+                "-c:v".to_string(),
+                "apng".to_string(),
+                "-pred".to_string(),
+                "mixed".to_string(),
+                "-compression_level".to_string(),
+                compression_level.to_string(),
+                "-plays".to_string(),
+                plays.to_string(),
+                "-f".to_string(),
+                "apng".to_string(),
+            ],
+            Self::Jpeg { .. } => return None,
+        };
+
+        Some(args)
+    }
+}
+
+/// Video output formats crunch knows how to encode.
+pub const VIDEO_FORMATS: &[&str] = &["webm", "mp4", "mov", "mkv", "av1"];
+
+/// Video encoders, each with its own crf scale and speed preset vocabulary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VideoCodec {
+    /// libx264, crf: 0-51, preset: ultrafast..veryslow
+    H264,
+    /// libx265, crf: 0-51, preset: ultrafast..veryslow
+    H265,
+    /// libsvtav1, crf: 0-63, preset: 0-13 (lower = slower, better)
+    Av1,
+    /// libvpx-vp9, crf: 0-63, deadline: good/best/realtime
+    Vp9,
+}
+
+/// A video codec paired with the container and audio codec it is written with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VideoProfile {
+    pub codec: VideoCodec,
+    /// Extension of the output file
+    pub container: &'static str,
+    /// e.g., "aac", "libopus"
+    pub audio_codec: &'static str,
+    /// The audio codec as ffmpeg reports it for an input, e.g. "opus" for libopus
+    pub audio_codec_name: &'static str,
+}
+
+impl VideoProfile {
+    /// Pick the codec, container and audio codec for `extension`.
+    pub fn for_format(extension: &str) -> Result<Self> {
+        let (codec, container, audio_codec, audio_codec_name) =
+            match extension.to_lowercase().as_str() {
+                "webm" => (VideoCodec::Vp9, "webm", "libopus", "opus"),
+                "mp4" => (VideoCodec::H264, "mp4", "aac", "aac"),
+                "mov" => (VideoCodec::H264, "mov", "aac", "aac"),
+                "mkv" => (VideoCodec::H265, "mkv", "libopus", "opus"),
+                // AV1 has no container of its own, webm is the one browsers play
+                "av1" => (VideoCodec::Av1, "webm", "libopus", "opus"),
+                other => bail!(
+                    "Unsupported video format '{}'. Supported: {}",
+                    other,
+                    VIDEO_FORMATS.join(", ")
+                ),
+            };
+
+        Ok(Self {
+            codec,
+            container,
+            audio_codec,
+            audio_codec_name,
+        })
+    }
+
+    /// Encoder arguments at `crf`, starting with `-c:v`.
+    pub fn args(&self, crf: u8, preset: &str) -> Vec<String> {
+        let mut args = self.codec.args(crf, preset);
+        args.extend(self.tag_args());
+        args
+    }
+
+    /// Encoder arguments at an average `bitrate`, see [`VideoCodec::bitrate_args`].
+    pub fn bitrate_args(
+        &self,
+        bitrate: u64,
+        preset: &str,
+        pass: Option<(u8, &str)>,
+    ) -> Vec<String> {
+        let mut args = self.codec.bitrate_args(bitrate, preset, pass);
+        args.extend(self.tag_args());
+        args
+    }
+
+    /// Lets QuickTime and Safari recognise HEVC in mp4/mov. Other containers, e.g.
+    /// matroska, refuse the tag.
+    fn tag_args(&self) -> Vec<String> {
+        if self.codec == VideoCodec::H265 && matches!(self.container, "mp4" | "mov") {
+            vec!["-tag:v".to_string(), "hvc1".to_string()]
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+impl VideoCodec {
+    /// The codec as ffmpeg reports it for an input, e.g. "hevc" for libx265.
+    pub fn codec_name(&self) -> &'static str {
+        match self {
+            Self::H264 => "h264",
+            Self::H265 => "hevc",
+            Self::Av1 => "av1",
+            Self::Vp9 => "vp9",
+        }
+    }
+
+    /// Roughly the bitrate (bits per second) `level`'s crf ends up at for typical footage
+    /// of this size, based on bits per pixel per frame. Used to tell whether an input is
+    /// already compressed as far as this level would take it.
+    pub fn typical_bitrate(&self, level: &str, width: u32, height: u32, fps: f64) -> u64 {
+        // For H.264, the others get the same quality from fewer bits
+        let bits_per_pixel = match level {
+            "low" => 0.1,
+            "high" => 0.045,
+            _ => 0.07,
+        };
+        let efficiency = match self {
+            Self::H264 => 1.0,
+            Self::H265 | Self::Vp9 => 0.6,
+            Self::Av1 => 0.5,
+        };
+
+        (width as f64 * height as f64 * fps * bits_per_pixel * efficiency) as u64
+    }
+
+    /// Name of the ffmpeg encoder, as passed to `-c:v`.
+    pub fn encoder(&self) -> &'static str {
+        match self {
+            Self::H264 => "libx264",
+            Self::H265 => "libx265",
+            Self::Av1 => "libsvtav1",
+            Self::Vp9 => "libvpx-vp9",
+        }
+    }
+
+    /// Map `level` onto this encoder's crf scale and preset vocabulary.
+    pub fn crf_and_preset(&self, level: &str) -> (u8, String) {
+        let (crf, preset) = match self {
+            Self::H264 => match level {
+                "low" => (20, "slow"),      // Low compression = high quality
+                "medium" => (24, "medium"), // Balanced
+                "high" => (28, "medium"),   // High compression = smaller size
+                _ => (24, "medium"),
+            },
+            Self::H265 => match level {
+                "low" => (22, "slow"),
+                "medium" => (26, "medium"),
+                "high" => (30, "medium"),
+                _ => (26, "medium"),
+            },
+            Self::Av1 => match level {
+                "low" => (28, "6"),
+                "medium" => (35, "8"),
+                "high" => (42, "8"),
+                _ => (35, "8"),
+            },
+            Self::Vp9 => match level {
+                "low" => (24, "good"),
+                "medium" => (33, "good"),
+                "high" => (42, "good"),
+                _ => (33, "good"),
+            },
+        };
+
+        (crf, preset.to_string())
+    }
+
+    /// Encoder arguments, starting with `-c:v`.
+    pub fn args(&self, crf: u8, preset: &str) -> Vec<String> {
+        let mut args = vec![
+            "-c:v".to_string(),
+            self.encoder().to_string(),
+            // CRITICAL: Force pixel format for Chrome/Web compatibility
+            "-pix_fmt".to_string(),
+            "yuv420p".to_string(),
+            "-crf".to_string(),
+            crf.to_string(),
+        ];
+
+        match self {
+            Self::H264 | Self::H265 | Self::Av1 => {
+                args.extend(["-preset".to_string(), preset.to_string()])
+            }
+            Self::Vp9 => args.extend([
+                // CRITICAL: VP9 requires -b:v 0 for CRF to work
+                "-b:v".to_string(),
+                "0".to_string(),
+                // Performance settings (makes encoding faster than default)
+                "-deadline".to_string(),
+                preset.to_string(),
+                "-cpu-used".to_string(),
+                "4".to_string(), // Range 0-5. 4 is a good balance of speed/size
+                "-row-mt".to_string(),
+                "1".to_string(), // Enable row-based multithreading
+            ]),
+        }
+
+        args
+    }
+
+    /// Whether ffmpeg can run this encoder in two passes. libsvtav1 only takes a
+    /// bitrate in a single pass.
+    pub fn supports_two_pass(&self) -> bool {
+        !matches!(self, Self::Av1)
+    }
+
+    /// Encoder arguments for an average `bitrate` (bits per second) instead of a crf,
+    /// starting with `-c:v`. With `pass`, for pass 1 or 2 of a two-pass encode that
+    /// keeps its statistics in files starting with `passlog`.
+    pub fn bitrate_args(
+        &self,
+        bitrate: u64,
+        preset: &str,
+        pass: Option<(u8, &str)>,
+    ) -> Vec<String> {
+        let mut args = vec![
+            "-c:v".to_string(),
+            self.encoder().to_string(),
+            "-pix_fmt".to_string(),
+            "yuv420p".to_string(),
+            "-b:v".to_string(),
+            bitrate.to_string(),
+        ];
+
+        match self {
+            Self::H264 | Self::H265 | Self::Av1 => {
+                args.extend(["-preset".to_string(), preset.to_string()])
+            }
+            Self::Vp9 => args.extend([
+                "-deadline".to_string(),
+                preset.to_string(),
+                "-cpu-used".to_string(),
+                "4".to_string(),
+                "-row-mt".to_string(),
+                "1".to_string(),
+            ]),
+        }
+
+        if let Some((pass, passlog)) = pass {
+            match self {
+                // x265 ignores -pass, and its option string splits on ':', so the path
+                // is quoted for drive letters
+                Self::H265 => args.extend([
+                    "-x265-params".to_string(),
+                    format!("pass={}:stats='{}'", pass, passlog),
+                ]),
+                _ => args.extend([
+                    "-pass".to_string(),
+                    pass.to_string(),
+                    "-passlogfile".to_string(),
+                    passlog.to_string(),
+                ]),
+            }
+        }
+
+        args
+    }
+}
+
+/// Audio output formats crunch knows how to encode.
+pub const AUDIO_FORMATS: &[&str] = &["mp3", "opus", "aac", "m4a", "flac", "ogg"];
+
+/// Audio encoders and the containers they are written to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioCodec {
+    /// libmp3lame, up to 48 kHz
+    Mp3,
+    /// libopus, only runs at 48/24/16/12/8 kHz
+    Opus,
+    /// ffmpeg's native aac encoder, for .aac and .m4a
+    Aac,
+    /// flac is lossless, so it takes a compression level instead of a bitrate
+    Flac,
+    /// libvorbis in an ogg container
+    Vorbis,
+}
+
+impl AudioCodec {
+    /// Pick the codec for `extension`.
+    pub fn for_format(extension: &str) -> Result<Self> {
+        let codec = match extension.to_lowercase().as_str() {
+            "mp3" => Self::Mp3,
+            "opus" => Self::Opus,
+            "aac" | "m4a" => Self::Aac,
+            "flac" => Self::Flac,
+            "ogg" => Self::Vorbis,
+            other => bail!(
+                "Unsupported audio format '{}'. Supported: {}",
+                other,
+                AUDIO_FORMATS.join(", ")
+            ),
+        };
+
+        Ok(codec)
+    }
+
+    /// The codec as ffmpeg reports it for an input, e.g. "mp3" for libmp3lame.
+    pub fn codec_name(&self) -> &'static str {
+        match self {
+            Self::Mp3 => "mp3",
+            Self::Opus => "opus",
+            Self::Aac => "aac",
+            Self::Flac => "flac",
+            Self::Vorbis => "vorbis",
+        }
+    }
+
+    /// Name of the ffmpeg encoder, as passed to `-c:a`.
+    pub fn encoder(&self) -> &'static str {
+        match self {
+            Self::Mp3 => "libmp3lame",
+            Self::Opus => "libopus",
+            Self::Aac => "aac",
+            Self::Flac => "flac",
+            Self::Vorbis => "libvorbis",
+        }
+    }
+
+    /// Map `level` onto this codec's bitrate ladder. `None` for lossless codecs.
+    pub fn bitrate(&self, level: &str) -> Option<String> {
+        let bitrate = match self {
+            Self::Mp3 => match level {
+                "low" => "192k",    // Low compression = high quality
+                "medium" => "128k", // Balanced
+                "high" => "64k",    // High compression = smaller size
+                _ => "128k",
+            },
+            Self::Opus => match level {
+                "low" => "128k",
+                "medium" => "96k",
+                "high" => "48k",
+                _ => "96k",
+            },
+            Self::Aac => match level {
+                "low" => "192k",
+                "medium" => "128k",
+                "high" => "96k",
+                _ => "128k",
+            },
+            Self::Vorbis => match level {
+                "low" => "160k",
+                "medium" => "112k",
+                "high" => "64k",
+                _ => "112k",
+            },
+            Self::Flac => return None,
+        };
+
+        Some(bitrate.to_string())
+    }
+
+    /// Map `level` onto the flac compression level (0-12). `None` for lossy codecs.
+    pub fn compression_level(&self, level: &str) -> Option<u8> {
+        match self {
+            Self::Flac => Some(match level {
+                "low" => 5,
+                "medium" => 8,
+                "high" => 12,
+                _ => 8,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Sample rate the output is forced to, when the codec can't take arbitrary input rates.
+    pub fn default_sample_rate(&self) -> Option<u32> {
+        match self {
+            Self::Opus => Some(48000),
+            _ => None,
+        }
+    }
+
+    /// Whether the encoder accepts `sample_rate`.
+    pub fn supports_sample_rate(&self, sample_rate: u32) -> bool {
+        match self {
+            Self::Opus => [48000, 24000, 16000, 12000, 8000].contains(&sample_rate),
+            Self::Mp3 => [48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000]
+                .contains(&sample_rate),
+            Self::Aac | Self::Vorbis => (8000..=96000).contains(&sample_rate),
+            Self::Flac => (1..=655350).contains(&sample_rate),
+        }
+    }
+}
+
+/// Parse an ffmpeg bitrate such as "128k" or "2M" into bits per second.
+pub fn parse_bitrate(bitrate: &str) -> Option<u64> {
+    let (number, multiplier) = match bitrate.char_indices().last()? {
+        (i, 'k' | 'K') => (&bitrate[..i], 1_000),
+        (i, 'M') => (&bitrate[..i], 1_000_000),
+        _ => (bitrate, 1),
+    };
+    let value: f64 = number.parse().ok()?;
+
+    Some((value * multiplier as f64) as u64)
+}
+
+/// The ffmpeg muxer that writes files with `extension`, as ffmpeg picks it from the output name.
+pub fn muxer_for_extension(extension: &str) -> Option<&'static str> {
+    let muxer = match extension.to_lowercase().as_str() {
+        "webp" => "webp",
+        "avif" => "avif",
+        "gif" => "gif",
+        // Single images are written by the image sequence muxer
+        "jpg" | "jpeg" | "png" => "image2",
+        "webm" => "webm",
+        "mp4" => "mp4",
+        "mov" => "mov",
+        "mkv" => "matroska",
+        "mp3" => "mp3",
+        "opus" => "opus",
+        "aac" => "adts",
+        "m4a" => "ipod",
+        "flac" => "flac",
+        "ogg" => "ogg",
+        _ => return None,
+    };
+
+    Some(muxer)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_image_encoder_per_format() {
+        assert_eq!(
+            ImageEncoder::for_format("webp", "medium").unwrap().codec(),
+            "libwebp"
+        );
+        assert_eq!(
+            ImageEncoder::for_format("avif", "medium").unwrap().codec(),
+            "libaom-av1"
+        );
+        assert_eq!(
+            ImageEncoder::for_format("jpg", "medium").unwrap().codec(),
+            "mjpeg"
+        );
+        assert_eq!(
+            ImageEncoder::for_format("JPEG", "medium").unwrap().codec(),
+            "mjpeg"
+        );
+        assert_eq!(
+            ImageEncoder::for_format("png", "medium").unwrap().codec(),
+            "png"
+        );
+        assert!(ImageEncoder::for_format("bmp", "medium").is_err());
+    }
+
+    #[test]
+    fn test_image_encoder_level_ordering() {
+        let webp = |level| match ImageEncoder::for_format("webp", level).unwrap() {
+            ImageEncoder::WebP { quality, .. } => quality,
+            _ => unreachable!(),
+        };
+        assert!(webp("low") > webp("medium"));
+        assert!(webp("medium") > webp("high"));
+        // Unknown levels fall back to medium
+        assert_eq!(webp("ultra"), webp("medium"));
+
+        let jpeg = |level| match ImageEncoder::for_format("jpg", level).unwrap() {
+            ImageEncoder::Jpeg { qscale } => qscale,
+            _ => unreachable!(),
+        };
+        assert!(jpeg("low") < jpeg("high"));
+    }
+
+    #[test]
+    fn test_image_encoder_args() {
+        let args = ImageEncoder::for_format("avif", "high").unwrap().args();
+        assert_eq!(&args[..2], ["-c:v", "libaom-av1"]);
+        assert!(args.windows(2).any(|w| w == ["-still-picture", "1"]));
+        assert!(args.windows(2).any(|w| w == ["-crf", "40"]));
+    }
+
+    #[test]
+    fn test_video_profile_per_format() {
+        let mp4 = VideoProfile::for_format("mp4").unwrap();
+        assert_eq!(mp4.codec, VideoCodec::H264);
+        assert_eq!(mp4.audio_codec, "aac");
+
+        let webm = VideoProfile::for_format("webm").unwrap();
+        assert_eq!(webm.codec, VideoCodec::Vp9);
+        assert_eq!(webm.audio_codec, "libopus");
+
+        let av1 = VideoProfile::for_format("av1").unwrap();
+        assert_eq!(av1.codec, VideoCodec::Av1);
+        assert_eq!(av1.container, "webm");
+
+        assert!(VideoProfile::for_format("gif").is_err());
+    }
+
+    #[test]
+    fn test_video_codec_args() {
+        let (crf, preset) = VideoCodec::H264.crf_and_preset("high");
+        let args = VideoCodec::H264.args(crf, &preset);
+        assert!(args.windows(2).any(|w| w == ["-c:v", "libx264"]));
+        assert!(args.windows(2).any(|w| w == ["-preset", "medium"]));
+        // VP9-only flags must not leak into other encoders
+        assert!(!args.iter().any(|a| a == "-deadline" || a == "-row-mt"));
+
+        let (crf, preset) = VideoCodec::Vp9.crf_and_preset("low");
+        let args = VideoCodec::Vp9.args(crf, &preset);
+        assert!(args.windows(2).any(|w| w == ["-deadline", "good"]));
+        assert!(args.windows(2).any(|w| w == ["-crf", "24"]));
+
+        // The matroska muxer rejects the hvc1 tag
+        let mkv = VideoProfile::for_format("mkv").unwrap();
+        let (crf, preset) = mkv.codec.crf_and_preset("medium");
+        let args = mkv.args(crf, &preset);
+        assert!(args.windows(2).any(|w| w == ["-c:v", "libx265"]));
+        assert!(!args.iter().any(|a| a == "-tag:v"));
+        assert!(
+            !mkv.bitrate_args(1_000_000, &preset, None)
+                .iter()
+                .any(|a| a == "-tag:v")
+        );
+    }
+
+    #[test]
+    fn test_audio_codec_per_format() {
+        assert_eq!(
+            AudioCodec::for_format("mp3").unwrap().encoder(),
+            "libmp3lame"
+        );
+        assert_eq!(AudioCodec::for_format("opus").unwrap().encoder(), "libopus");
+        assert_eq!(AudioCodec::for_format("m4a").unwrap().encoder(), "aac");
+        assert_eq!(
+            AudioCodec::for_format("ogg").unwrap().encoder(),
+            "libvorbis"
+        );
+        assert!(AudioCodec::for_format("wav").is_err());
+    }
+
+    #[test]
+    fn test_audio_codec_settings() {
+        let flac = AudioCodec::Flac;
+        assert_eq!(flac.bitrate("medium"), None);
+        assert_eq!(flac.compression_level("high"), Some(12));
+
+        let opus = AudioCodec::Opus;
+        assert_eq!(opus.bitrate("high").as_deref(), Some("48k"));
+        assert_eq!(opus.default_sample_rate(), Some(48000));
+        assert!(!opus.supports_sample_rate(44100));
+        assert!(AudioCodec::Mp3.supports_sample_rate(44100));
+    }
+
+    #[test]
+    fn test_parse_bitrate() {
+        assert_eq!(parse_bitrate("128k"), Some(128_000));
+        assert_eq!(parse_bitrate("2.5M"), Some(2_500_000));
+        assert_eq!(parse_bitrate("96000"), Some(96_000));
+        assert_eq!(parse_bitrate("fast"), None);
+    }
+
+    #[test]
+    fn test_typical_bitrate() {
+        let h264 = VideoCodec::H264.typical_bitrate("medium", 1920, 1080, 30.0);
+        // A few Mbit/s for 1080p30, as a crf 24 encode usually ends up
+        assert!((3_000_000..6_000_000).contains(&h264));
+        assert!(VideoCodec::H264.typical_bitrate("high", 1920, 1080, 30.0) < h264);
+        assert!(VideoCodec::Av1.typical_bitrate("medium", 1920, 1080, 30.0) < h264);
+    }
+}
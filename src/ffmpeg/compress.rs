@@ -1,11 +1,26 @@
-use super::progress_bar::init_progress_bar;
+use super::animation::{AnimatedFormat, AnimatedOutput};
+use super::batch::{Batch, Target, run_batch};
+use super::codec::{AudioCodec, ImageEncoder, VideoCodec, VideoProfile, parse_bitrate};
+use super::image_metadata;
+use super::metadata::MetadataPolicy;
+use super::partial::PartialOutput;
+use super::probe::MediaInfo;
+use super::responsive::{Responsive, Variant};
+use super::runner::{run_ffmpeg, run_ffmpeg_with_progress};
+use super::scale::Resize;
+use super::target_size::TargetSize;
+use crate::consts::IMAGE_EXTENSIONS;
+use crate::manifest::Manifest;
+use crate::summary::{FileRecord, MediaKind};
 use anyhow::{Context, Result, bail};
-use indicatif::ParallelProgressIterator;
-use rayon::prelude::*;
+use indicatif::ProgressBar;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::sync::Arc;
 
+#[derive(Clone)]
 pub struct BaseCompressOptions {
     /// the path of folder which store needed media files
     pub input_path: PathBuf,
@@ -14,6 +29,41 @@ pub struct BaseCompressOptions {
     pub output_extension: String,
     pub output_prefix: Option<String>,
     pub level: String,
+    /// What to do with outputs that aren't smaller than their source, None = keep them
+    pub never_grow: Option<NoGainAction>,
+    /// How much smaller (in percent) an output must be to be kept under `never_grow`
+    pub min_saving: u8,
+    /// Skip inputs whose output already exists and is newer than the input
+    pub incremental: bool,
+    /// Downscaling for images and videos, audio ignores it
+    pub resize: Resize,
+    /// Which tags, chapters and ICC profiles outputs keep
+    pub metadata: MetadataPolicy,
+    /// Progress of the run, shared by every media type, for `--resume`
+    pub manifest: Option<Arc<Manifest>>,
+}
+
+/// What `--never-grow` does with an output that isn't smaller than its source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoGainAction {
+    /// Delete the output and record the file as skipped
+    Skip,
+    /// Delete the output and copy the original through in its place
+    Copy,
+}
+
+/// What a single `compress_*` call produced.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// The compressed file was written to this path
+    Compressed(PathBuf),
+    /// A `--responsive` set was written to these paths, narrowest first
+    CompressedSet(Vec<PathBuf>),
+    /// The original was copied to this path instead of being re-encoded, either as it
+    /// is or with its streams moved unchanged into the target container, with the reason
+    Copied(PathBuf, String),
+    /// Nothing was written, with the reason
+    Skipped(String),
 }
 
 impl BaseCompressOptions {
@@ -31,55 +81,338 @@ impl BaseCompressOptions {
             output_extension,
             output_prefix: None,
             level: "medium".to_string(),
+            never_grow: None,
+            min_saving: 0,
+            incremental: false,
+            resize: Resize::default(),
+            metadata: MetadataPolicy::default(),
+            manifest: None,
+        }
+    }
+
+    /// Where the compressed version of `input` is written, creating its directory.
+    pub fn output_file(&self, input: &Path) -> Result<PathBuf> {
+        self.output_file_with_extension(input, &self.output_extension)
+    }
+
+    pub fn output_file_with_extension(&self, input: &Path, extension: &str) -> Result<PathBuf> {
+        // Calculate relative path from input_path to input file
+        // e.g., input = "./a/b/girl.mp3", input_path = "./" → relative = "a/b/girl.mp3"
+        let relative_path = input.strip_prefix(&self.input_path).unwrap_or(input);
+
+        // Get relative directory (parent of the file)
+        // e.g., "a/b/girl.mp3" → "a/b"
+        let relative_dir = relative_path.parent().unwrap_or(Path::new(""));
+
+        // Build output directory: output_path + relative directory
+        // e.g., "./crunch_compress" + "a/b" → "./crunch_compress/a/b"
+        let output_dir = self.output_path.join(relative_dir);
+
+        // Create output directory if it doesn't exist
+        fs::create_dir_all(&output_dir).context("Failed to create output directory")?;
+
+        // Build output filename
+        let stem = input
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or("output");
+
+        let prefix = match &self.output_prefix {
+            Some(p) => format!("{}_", p),
+            None => String::new(),
+        };
+
+        let new_filename = format!("{}{}.{}", prefix, stem, extension);
+        let mut output = output_dir.join(new_filename);
+
+        // Avoid overwriting the input
+        if input == output {
+            let new_filename = format!("compressed_{}{}.{}", prefix, stem, extension);
+            output = output_dir.join(new_filename);
+        }
+
+        Ok(output)
+    }
+
+    /// The path of the `width` wide image of a `--responsive` set,
+    /// e.g. "out/hero.webp" → "out/hero-640w.webp".
+    pub fn variant_file(&self, output: &Path, width: u32) -> PathBuf {
+        let stem = output
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or("output");
+        output.with_file_name(format!("{}-{}w.{}", stem, width, self.output_extension))
+    }
+
+    /// In incremental mode, whether `input` was already compressed with `codec` by an earlier
+    /// run: its output exists and is newer than the input. Under `--never-grow=copy` so does
+    /// a copied original, if the manifest says this target copied it, as another format or
+    /// a file of the same name could have written it just as well.
+    pub fn is_up_to_date(&self, input: &Path, codec: &str, output: &Path) -> Result<bool> {
+        if !self.incremental {
+            return Ok(false);
+        }
+
+        let input_modified = fs::metadata(input)?.modified()?;
+        let is_newer = |path: &Path| {
+            fs::metadata(path)
+                .and_then(|m| m.modified())
+                .is_ok_and(|modified| modified >= input_modified)
+        };
+
+        if is_newer(output) {
+            return Ok(true);
+        }
+
+        if self.never_grow == Some(NoGainAction::Copy)
+            && let Some(manifest) = &self.manifest
+        {
+            let copy = self.copy_file(input)?;
+            return Ok(manifest.output(input, codec) == Some(copy.clone()) && is_newer(&copy));
+        }
+
+        Ok(false)
+    }
+
+    /// `self`, or when the original copied through by `--never-grow=copy` would land on one
+    /// of `taken` (e.g. the jpg output of `--images=webp,jpg` for a JPEG input), a copy that
+    /// skips instead.
+    fn without_copy_over(&self, input: &Path, taken: &[&Path]) -> Result<Cow<'_, Self>> {
+        if self.never_grow == Some(NoGainAction::Copy)
+            && taken.contains(&self.copy_file(input)?.as_path())
+        {
+            return Ok(Cow::Owned(Self {
+                never_grow: Some(NoGainAction::Skip),
+                ..self.clone()
+            }));
+        }
+        Ok(Cow::Borrowed(self))
+    }
+
+    /// Where `copy_original` puts `input`: the output directory, with the input's extension.
+    fn copy_file(&self, input: &Path) -> Result<PathBuf> {
+        let extension = input
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or_default();
+        self.output_file_with_extension(input, extension)
+    }
+
+    /// Whether `input` already has the output extension, so it can be kept as it is.
+    pub fn has_output_extension(&self, input: &Path) -> bool {
+        input
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| ext.eq_ignore_ascii_case(&self.output_extension))
+    }
+
+    /// Record in the manifest (if any) that `output` is about to be written with `codec`,
+    /// so an interrupted run knows to discard it. `output` is the temporary file ffmpeg writes to.
+    pub fn mark_started(&self, input: &Path, codec: &str, output: &Path) -> Result<()> {
+        match &self.manifest {
+            Some(manifest) => manifest.start(input, codec, output),
+            None => Ok(()),
+        }
+    }
+
+    /// Apply `never_grow` to a freshly written `output`: keep it only when it is
+    /// at least `min_saving` percent smaller than `input`.
+    fn check_gain(
+        &self,
+        ffmpeg: &Path,
+        input: &Path,
+        info: &MediaInfo,
+        codec: &str,
+        output: PathBuf,
+    ) -> Result<Outcome> {
+        let Some(action) = self.never_grow else {
+            return Ok(Outcome::Compressed(output));
+        };
+
+        let input_size = fs::metadata(input)?.len();
+        let output_size = fs::metadata(&output)?.len();
+
+        // e.g. min_saving = 10 keeps outputs of up to 90% of the input size
+        let limit = input_size as u128 * (100 - self.min_saving.min(100) as u128) / 100;
+        if output_size < input_size && output_size as u128 <= limit {
+            return Ok(Outcome::Compressed(output));
+        }
+
+        fs::remove_file(&output).context("Failed to remove output")?;
+
+        match action {
+            NoGainAction::Skip => Ok(Outcome::Skipped("no gain".to_string())),
+            NoGainAction::Copy => self.copy_original(ffmpeg, input, info, codec, "no gain"),
+        }
+    }
+
+    /// Leave an input that re-encoding wouldn't improve alone. It is skipped, or
+    /// copied through with `--never-grow=copy`.
+    fn keep_original(
+        &self,
+        ffmpeg: &Path,
+        input: &Path,
+        info: &MediaInfo,
+        codec: &str,
+        reason: &str,
+    ) -> Result<Outcome> {
+        match self.never_grow {
+            Some(NoGainAction::Copy) => self.copy_original(ffmpeg, input, info, codec, reason),
+            _ => Ok(Outcome::Skipped(reason.to_string())),
+        }
+    }
+
+    /// Copy the streams of `input` unchanged into `output`, applying the metadata policy.
+    /// `container_args` are the output container's own options.
+    fn remux(
+        &self,
+        ffmpeg: &Path,
+        input: &Path,
+        info: &MediaInfo,
+        codec: &str,
+        output: &Path,
+        container_args: &[String],
+    ) -> Result<PathBuf> {
+        let partial = PartialOutput::new(output);
+        self.mark_started(input, codec, partial.path())?;
+
+        let args = self.remux_args(input, info, container_args, partial.path())?;
+        let result = run_ffmpeg(ffmpeg, &args)?;
+        if !result.status.success() {
+            let stderr = String::from_utf8_lossy(&result.stderr);
+            bail!("Failed to remux {}: {}", input.display(), stderr)
         }
+
+        partial.persist()
+    }
+
+    fn remux_args(
+        &self,
+        input: &Path,
+        info: &MediaInfo,
+        container_args: &[String],
+        output: &Path,
+    ) -> Result<Vec<String>> {
+        let mut args = vec![
+            "-i".to_string(),
+            input.to_str().context("Invalid input path")?.to_string(),
+            "-c".to_string(),
+            "copy".to_string(),
+        ];
+        args.extend(self.metadata.args(info));
+        args.extend_from_slice(container_args);
+        args.push("-y".to_string());
+        args.push(output.to_str().context("Invalid output path")?.to_string());
+        Ok(args)
+    }
+
+    /// Copy `input` to the output directory, keeping its extension, without the metadata
+    /// the policy drops: JPEG, PNG and WebP images are rewritten without it, videos and
+    /// audio have their streams copied by ffmpeg. Other images are skipped for `reason`
+    /// unless every tag is kept, as only re-encoding could drop theirs.
+    fn copy_original(
+        &self,
+        ffmpeg: &Path,
+        input: &Path,
+        info: &MediaInfo,
+        codec: &str,
+        reason: &str,
+    ) -> Result<Outcome> {
+        let extension = input
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or_default();
+        let copy = self.copy_file(input)?;
+
+        if self.metadata == MetadataPolicy::Keep {
+            let partial = PartialOutput::new(&copy);
+            fs::copy(input, partial.path()).context("Failed to copy original")?;
+            return Ok(Outcome::Copied(partial.persist()?, reason.to_string()));
+        }
+
+        if IMAGE_EXTENSIONS.contains(&extension.to_lowercase().as_str()) {
+            let data = fs::read(input).context("Failed to read original")?;
+            let Some(stripped) = image_metadata::strip(&data, self.metadata) else {
+                return Ok(Outcome::Skipped(format!(
+                    "{}, and the original can't be copied without its metadata",
+                    reason
+                )));
+            };
+            let partial = PartialOutput::new(&copy);
+            fs::write(partial.path(), stripped).context("Failed to copy original")?;
+            return Ok(Outcome::Copied(partial.persist()?, reason.to_string()));
+        }
+
+        let copy = self.remux(ffmpeg, input, info, codec, &copy, &[])?;
+        Ok(Outcome::Copied(copy, reason.to_string()))
     }
 }
 
 pub struct ImageCompressOptions {
-    pub quality: u8,
-    pub compression_level: u8,
+    pub encoder: ImageEncoder,
+    /// Export a set of widths per image instead of a single image. The same for every
+    /// format of a run, as one ffmpeg run writes them all
+    pub responsive: Option<Responsive>,
+    /// What animated inputs are turned into, the same for every format of a run
+    pub animated: AnimatedOutput,
     pub base: BaseCompressOptions,
 }
 
 impl Default for ImageCompressOptions {
     fn default() -> Self {
         Self {
-            quality: 1,
-            compression_level: 6,
+            encoder: ImageEncoder::WebP {
+                quality: 75,
+                compression_level: 5,
+            },
+            responsive: None,
+            animated: AnimatedOutput::default(),
             base: BaseCompressOptions {
-                input_path: PathBuf::from("."),
-                output_path: PathBuf::from("."),
-                output_extension: "webp".into(),
                 output_prefix: Some("compressed".to_string()),
-                level: "medium".to_string(),
+                ..BaseCompressOptions::new_with("image")
             },
         }
     }
 }
 
 impl ImageCompressOptions {
-    pub fn with_base(base: BaseCompressOptions) -> Self {
-        // quality: lower = better quality (webp qscale)
-        // compression_level: 0-6, higher = more compression effort
-        let (quality, compression_level) = match base.level.as_str() {
-            "low" => (5, 4),     // Low compression = high quality
-            "medium" => (30, 5), // Balanced
-            "high" => (60, 6),   // High compression = smaller size
-            _ => (30, 5),        // Default to medium
-        };
-
-        Self {
-            quality,
-            compression_level,
+    pub fn with_base(base: BaseCompressOptions) -> Result<Self> {
+        // The encoder (libwebp, libaom-av1, mjpeg, png) follows the output extension,
+        // and --level is mapped onto that encoder's own quality scale.
+        let encoder = ImageEncoder::for_format(&base.output_extension, &base.level)?;
+
+        Ok(Self {
+            encoder,
+            responsive: None,
+            animated: AnimatedOutput::default(),
             base,
+        })
+    }
+
+    /// The encoder settings as name/value pairs, for reports.
+    pub fn settings(&self) -> BTreeMap<String, String> {
+        let mut settings = self.encoder.settings();
+        settings.insert("level".to_string(), self.base.level.clone());
+        settings.insert(
+            "metadata".to_string(),
+            self.base.metadata.as_str().to_string(),
+        );
+        settings.insert("animated".to_string(), self.animated.as_str().to_string());
+        settings.extend(self.base.resize.settings());
+        if let Some(responsive) = &self.responsive {
+            let widths: Vec<String> = responsive.widths.iter().map(u32::to_string).collect();
+            settings.insert("widths".to_string(), widths.join(","));
         }
+        settings
     }
 }
 
 pub struct VideoCompressOptions {
-    pub crf: u8,        // Constant Rate Factor (0-51, lower is better quality). Default: 23
-    pub preset: String, // ultrafast, superfast, veryfast, faster, fast, medium, slow, slower, veryslow
-    pub video_codec: String, // e.g., "libx264", "libx265"
+    pub crf: u8, // Constant Rate Factor, the scale depends on the codec (0-51 for x264/x265, 0-63 for VP9/AV1)
+    pub preset: String, // Speed preset in the codec's own vocabulary, e.g. "medium" for x264, "good" for VP9
+    pub profile: VideoProfile, // Video codec, container and matching audio codec
+    pub audio_bitrate: String, // e.g., "64k", "128k"
+    pub target_size: Option<TargetSize>, // Encode to a bitrate that fits this size instead of the crf
     pub base: BaseCompressOptions,
 }
 
@@ -88,88 +421,226 @@ impl Default for VideoCompressOptions {
         Self {
             crf: 42,
             preset: "good".to_string(),
-            video_codec: "libvpx-vp9".to_string(),
+            profile: VideoProfile {
+                codec: VideoCodec::Vp9,
+                container: "webm",
+                audio_codec: "libopus",
+                audio_codec_name: "opus",
+            },
+            audio_bitrate: "64k".to_string(),
+            target_size: None,
             base: BaseCompressOptions {
-                input_path: PathBuf::from("."),
-                output_path: PathBuf::from("."),
-                output_extension: "webm".to_string(),
                 output_prefix: Some("compressed".to_string()),
-                level: "medium".to_string(),
+                ..BaseCompressOptions::new_with("video")
             },
         }
     }
 }
 
 impl VideoCompressOptions {
-    pub fn with_base(base: BaseCompressOptions) -> Self {
-        // crf: 0-63 for VP9, lower = better quality
-        // preset: "good", "best", "realtime" for libvpx-vp9
-        let (crf, preset) = match base.level.as_str() {
-            "low" => (24, "good".to_string()), // Low compression = high quality
-            "medium" => (33, "good".to_string()), // Balanced
-            "high" => (42, "good".to_string()), // High compression = smaller size
-            _ => (33, "good".to_string()),
+    pub fn with_base(mut base: BaseCompressOptions) -> Result<Self> {
+        let profile = VideoProfile::for_format(&base.output_extension)?;
+        let (crf, preset) = profile.codec.crf_and_preset(&base.level);
+
+        // Opus stays transparent at lower bitrates than AAC
+        let audio_bitrate = match profile.audio_codec {
+            "libopus" => "64k",
+            _ => "128k",
         };
 
-        Self {
+        // e.g. --videos=av1 is written to a .webm file
+        base.output_extension = profile.container.to_string();
+
+        Ok(Self {
             crf,
             preset,
-            video_codec: "libvpx-vp9".to_string(),
+            profile,
+            audio_bitrate: audio_bitrate.to_string(),
+            target_size: None,
             base,
+        })
+    }
+
+    /// The encoder settings as name/value pairs, for reports.
+    pub fn settings(&self) -> BTreeMap<String, String> {
+        let mut settings = BTreeMap::from([
+            ("level".to_string(), self.base.level.clone()),
+            ("crf".to_string(), self.crf.to_string()),
+            ("preset".to_string(), self.preset.clone()),
+            ("container".to_string(), self.profile.container.to_string()),
+            (
+                "audio_codec".to_string(),
+                self.profile.audio_codec.to_string(),
+            ),
+            ("audio_bitrate".to_string(), self.audio_bitrate.clone()),
+            (
+                "metadata".to_string(),
+                self.base.metadata.as_str().to_string(),
+            ),
+        ]);
+        settings.extend(self.base.resize.settings());
+        // The bitrate is worked out per file, the crf isn't used
+        if let Some(target_size) = self.target_size {
+            settings.remove("crf");
+            settings.insert("target_size".to_string(), target_size.bytes().to_string());
+        }
+        settings
+    }
+
+    /// Options of the target container rather than of the encoders.
+    fn container_args(&self) -> Vec<String> {
+        // Move the index to the front so playback can start before the download finishes
+        if !matches!(self.profile.container, "mp4" | "mov") {
+            return Vec::new();
+        }
+        let mut movflags = "+faststart".to_string();
+        if let Some(flags) = self.base.metadata.movflags() {
+            movflags.push_str(flags);
+        }
+        vec!["-movflags".to_string(), movflags]
+    }
+
+    /// Whether `info` is already in the target codecs, at no more than the bitrate
+    /// this level would produce, so re-encoding would only lose quality.
+    pub fn is_already_optimal(&self, info: &MediaInfo) -> bool {
+        let codec = self.profile.codec;
+        let (Some(width), Some(height)) = (info.width, info.height) else {
+            return false;
+        };
+        if info.video_codec.as_deref() != Some(codec.codec_name()) {
+            return false;
+        }
+        // It still has to be scaled down
+        if self.base.resize.target_size(width, height, true).is_some() {
+            return false;
+        }
+
+        let target =
+            codec.typical_bitrate(&self.base.level, width, height, info.fps.unwrap_or(30.0));
+        let Some(bitrate) = info.video_bitrate.or(info.bitrate) else {
+            return false;
+        };
+        if bitrate > target {
+            return false;
+        }
+
+        // The audio has to be fine as it is too
+        match info.audio_codec.as_deref() {
+            None => true,
+            Some(audio_codec) => {
+                audio_codec == self.profile.audio_codec_name
+                    && info
+                        .audio_bitrate
+                        .zip(parse_bitrate(&self.audio_bitrate))
+                        .is_some_and(|(bitrate, target)| bitrate <= target)
+            }
         }
     }
 }
 
 pub struct AudioCompressOptions {
-    pub bitrate: String,          // e.g., "64k", "128k", "32k"
-    pub audio_codec: String,      // e.g., "libmp3lame", "libopus", "aac"
-    pub channels: Option<u8>,     // 1 = mono, 2 = stereo, None = keep original
-    pub sample_rate: Option<u32>, // e.g., 44100, 22050, None = keep original
+    pub codec: AudioCodec,             // Picked from the output extension
+    pub bitrate: Option<String>,       // e.g., "64k", "128k", "32k", None for lossless codecs
+    pub compression_level: Option<u8>, // flac only, 0-12
+    pub channels: Option<u8>,          // 1 = mono, 2 = stereo, None = keep original
+    pub sample_rate: Option<u32>,      // e.g., 44100, 22050, None = keep original
     pub base: BaseCompressOptions,
 }
 
 impl Default for AudioCompressOptions {
     fn default() -> Self {
         Self {
-            bitrate: "64k".to_string(),
-            audio_codec: "libmp3lame".to_string(),
+            bitrate: Some("64k".to_string()),
+            codec: AudioCodec::Mp3,
+            compression_level: None,
             channels: None,
             sample_rate: None,
             base: BaseCompressOptions {
-                input_path: PathBuf::from("."),
-                output_path: PathBuf::from("."),
-                output_extension: "mp3".to_string(),
                 output_prefix: Some("compressed".to_string()),
-                level: "medium".to_string(),
+                ..BaseCompressOptions::new_with("audio")
             },
         }
     }
 }
 
 impl AudioCompressOptions {
-    pub fn with_base(base: BaseCompressOptions) -> Self {
-        // bitrate: higher = better quality, larger file
-        let bitrate = match base.level.as_str() {
-            "low" => "192k".to_string(),    // Low compression = high quality
-            "medium" => "128k".to_string(), // Balanced
-            "high" => "64k".to_string(),    // High compression = smaller size
-            _ => "128k".to_string(),
-        };
-
-        Self {
-            bitrate,
-            audio_codec: "libmp3lame".to_string(),
+    pub fn with_base(base: BaseCompressOptions) -> Result<Self> {
+        // The codec follows the output extension, e.g. opus -> libopus at 48 kHz.
+        // Lossy codecs map --level onto a bitrate, flac onto a compression level.
+        let codec = AudioCodec::for_format(&base.output_extension)?;
+
+        Ok(Self {
+            bitrate: codec.bitrate(&base.level),
+            compression_level: codec.compression_level(&base.level),
+            codec,
             channels: None,
-            sample_rate: None,
+            sample_rate: codec.default_sample_rate(),
             base,
+        })
+    }
+
+    /// The encoder settings as name/value pairs, for reports.
+    pub fn settings(&self) -> BTreeMap<String, String> {
+        let mut settings = BTreeMap::from([
+            ("level".to_string(), self.base.level.clone()),
+            (
+                "metadata".to_string(),
+                self.base.metadata.as_str().to_string(),
+            ),
+        ]);
+
+        if let Some(bitrate) = &self.bitrate {
+            settings.insert("bitrate".to_string(), bitrate.clone());
+        }
+        if let Some(compression_level) = self.compression_level {
+            settings.insert(
+                "compression_level".to_string(),
+                compression_level.to_string(),
+            );
+        }
+        if let Some(channels) = self.channels {
+            settings.insert("channels".to_string(), channels.to_string());
+        }
+        if let Some(sample_rate) = self.sample_rate {
+            settings.insert("sample_rate".to_string(), sample_rate.to_string());
+        }
+
+        settings
+    }
+
+    /// Whether `info` is already in the target codec, at no more than the target bitrate,
+    /// so re-encoding would only lose quality.
+    pub fn is_already_optimal(&self, info: &MediaInfo) -> bool {
+        if info.audio_codec.as_deref() != Some(self.codec.codec_name()) {
+            return false;
+        }
+        if self
+            .channels
+            .is_some_and(|channels| info.channels != Some(channels))
+            || self
+                .sample_rate
+                .is_some_and(|rate| info.sample_rate != Some(rate))
+        {
+            return false;
+        }
+
+        match &self.bitrate {
+            Some(target) => info
+                .audio_bitrate
+                .or(info.bitrate)
+                .zip(parse_bitrate(target))
+                .is_some_and(|(bitrate, target)| bitrate <= target),
+            // A lossless input is as small as the lossless target
+            None => true,
         }
     }
 
     #[allow(unused)]
     pub fn set_bitrate(&mut self, bitrate: String) -> Self {
         Self {
-            bitrate,
-            audio_codec: self.audio_codec.clone(),
+            bitrate: Some(bitrate),
+            codec: self.codec,
+            compression_level: self.compression_level,
             channels: self.channels,
             sample_rate: self.sample_rate,
             base: BaseCompressOptions {
@@ -177,71 +648,82 @@ impl AudioCompressOptions {
                 output_path: PathBuf::from("./"),
                 output_prefix: Some("compressed".to_string()),
                 output_extension: self.base.output_extension.clone(),
-                level: "midium".to_string(),
+                ..BaseCompressOptions::new_with("audio")
             },
         }
     }
 }
-/// Calculates the area of a rectangle.
+/// Compress a single audio file, reporting encode progress on `bar`.
 ///
 /// # Arguments
 /// * `input` - The path of the single audio file
+/// * `info` - What probing `input` found
+/// * `bar` - The per-file progress bar driven by ffmpeg's `-progress` output
 pub fn compress_audio(
     ffmpeg: &Path,
     input: &Path,
+    info: &MediaInfo,
     options: &AudioCompressOptions,
-) -> Result<PathBuf> {
+    bar: &ProgressBar,
+) -> Result<Outcome> {
     if !ffmpeg.exists() {
         bail!("FFmpeg executable not found at: {}", ffmpeg.display());
     }
 
-    // Calculate relative path from input_path to input file
-    // e.g., input = "./a/b/girl.mp3", input_path = "./" → relative = "a/b/girl.mp3"
-    let relative_path = input
-        .strip_prefix(&options.base.input_path)
-        .unwrap_or(input);
-
-    // Get relative directory (parent of the file)
-    // e.g., "a/b/girl.mp3" → "a/b"
-    let relative_dir = relative_path.parent().unwrap_or(Path::new(""));
-
-    // Build output directory: output_path + relative directory
-    // e.g., "./crunch_compress" + "a/b" → "./crunch_compress/a/b"
-    let output_dir = options.base.output_path.join(relative_dir);
-
-    // Create output directory if it doesn't exist
-    fs::create_dir_all(&output_dir).context("Failed to create output directory")?;
-
-    // Build output filename
-    let stem = input
-        .file_stem()
-        .and_then(|s| s.to_str())
-        .unwrap_or("output");
-
-    let prefix = match &options.base.output_prefix {
-        Some(p) => format!("{}_", p),
-        None => String::new(),
-    };
-
-    let new_filename = format!("{}{}.{}", prefix, stem, options.base.output_extension);
-    let mut output = output_dir.join(new_filename);
-
-    if input == output {
-        let new_filename = format!(
-            "compressed_{}{}.{}",
-            prefix, stem, options.base.output_extension
+    let output = options.base.output_file(input)?;
+    if options
+        .base
+        .is_up_to_date(input, options.codec.encoder(), &output)?
+    {
+        return Ok(Outcome::Skipped("up to date".to_string()));
+    }
+    if options.is_already_optimal(info) {
+        if !options.base.has_output_extension(input) {
+            // Only the container is wrong, e.g. AAC in .m4a for --audios=aac
+            let container_args = ["-vn".to_string()];
+            let output = options.base.remux(
+                ffmpeg,
+                input,
+                info,
+                options.codec.encoder(),
+                &output,
+                &container_args,
+            )?;
+            return Ok(Outcome::Copied(output, "already optimal".to_string()));
+        }
+        return options.base.keep_original(
+            ffmpeg,
+            input,
+            info,
+            options.codec.encoder(),
+            "already optimal",
         );
-        output = output_dir.join(new_filename);
     }
+    let partial = PartialOutput::new(&output);
+    options
+        .base
+        .mark_started(input, options.codec.encoder(), partial.path())?;
 
     let mut args = vec![
         "-i".to_string(),
         input.to_str().context("Invalid input path")?.to_string(),
+        // Drop embedded cover art so it isn't re-encoded as a video stream
+        "-vn".to_string(),
         "-c:a".to_string(),
-        options.audio_codec.clone(),
-        "-b:a".to_string(),
-        options.bitrate.clone(),
+        options.codec.encoder().to_string(),
     ];
+    args.extend(options.base.metadata.args(info));
+
+    // Lossy codecs are driven by bitrate, flac by compression level
+    if let Some(bitrate) = &options.bitrate {
+        args.push("-b:a".to_string());
+        args.push(bitrate.clone());
+    }
+
+    if let Some(compression_level) = options.compression_level {
+        args.push("-compression_level".to_string());
+        args.push(compression_level.to_string());
+    }
 
     // Add channels if specified
     if let Some(channels) = options.channels {
@@ -251,21 +733,34 @@ pub fn compress_audio(
 
     // Add sample rate if specified
     if let Some(sample_rate) = options.sample_rate {
+        if !options.codec.supports_sample_rate(sample_rate) {
+            bail!(
+                "{} does not support a sample rate of {} Hz",
+                options.codec.encoder(),
+                sample_rate
+            );
+        }
         args.push("-ar".to_string());
         args.push(sample_rate.to_string());
     }
 
     // Overwrite output and add output path
     args.push("-y".to_string());
-    args.push(output.to_str().context("Invalid output path")?.to_string());
+    args.push(
+        partial
+            .path()
+            .to_str()
+            .context("Invalid output path")?
+            .to_string(),
+    );
 
-    let result = Command::new(ffmpeg)
-        .args(&args)
-        .output()
-        .context("Failed to execute ffmpeg")?;
+    let result = run_ffmpeg_with_progress(ffmpeg, &args, info.duration, bar)?;
 
     if result.status.success() {
-        Ok(output)
+        let output = partial.persist()?;
+        options
+            .base
+            .check_gain(ffmpeg, input, info, options.codec.encoder(), output)
     } else {
         let stderr = String::from_utf8_lossy(&result.stderr);
         bail!("Failed to compress {}: {}", input.display(), stderr)
@@ -274,233 +769,586 @@ pub fn compress_audio(
 
 pub fn compress_all_audios(
     ffmpeg: &Path,
-    audios: &Vec<PathBuf>,
+    audios: &[PathBuf],
     options: &AudioCompressOptions,
-) -> Result<Vec<Result<PathBuf>>> {
-    let count = audios.len() as u64;
-    let pb = init_progress_bar(count);
-
-    let results: Vec<Result<PathBuf>> = audios
-        .par_iter()
-        .map(|audio| {
-            let name = audio.file_name().unwrap_or_default().to_string_lossy();
-            pb.println(format!("Processing: {}", name));
-
-            let start = std::time::Instant::now();
-            let res = compress_audio(ffmpeg, audio, options);
-            let duration = start.elapsed();
-
-            match &res {
-                Ok(_) => pb.println(format!("Finished: {} (took {:.1?})", name, duration)),
-                Err(e) => {
-                    pb.println(format!("FAILED: {} \nReason: {}", name, e));
-                }
-            }
+) -> Result<Vec<FileRecord>> {
+    let batch = Batch {
+        ffmpeg,
+        kind: MediaKind::Audio,
+        targets: vec![Target {
+            base: &options.base,
+            codec: options.codec.encoder(),
+            settings: options.settings(),
+        }],
+        file_bars: true,
+        finish_message: "Audio Compression complete",
+    };
 
-            res
-        })
-        .progress_with(pb.clone())
-        .collect();
+    Ok(run_batch(audios, batch, |audio, info, _, bar| {
+        compress_audio(ffmpeg, audio, info, options, bar).map(|outcome| vec![outcome])
+    }))
+}
 
-    pb.finish_with_message("Audio Compression complete");
+/// Compress a single image to every format in `targets`. The formats that aren't up
+/// to date are written by one ffmpeg run, so the image is decoded only once.
+///
+/// Returns one outcome per target.
+pub fn compress_image(
+    ffmpeg: &Path,
+    input: &Path,
+    info: &MediaInfo,
+    targets: &[&ImageCompressOptions],
+) -> Result<Vec<Outcome>> {
+    if !ffmpeg.exists() {
+        bail!("FFmpeg executable not found at: {}", ffmpeg.display());
+    }
+
+    let paths = targets
+        .iter()
+        .map(|options| options.base.output_file(input))
+        .collect::<Result<Vec<_>>>()?;
+    let mut outcomes = Vec::new();
+    let mut outputs = Vec::new();
+    for (index, options) in targets.iter().enumerate() {
+        let output = paths[index].clone();
+        if options
+            .base
+            .is_up_to_date(input, options.encoder.codec(), &output)?
+        {
+            outcomes.push(Some(Outcome::Skipped("up to date".to_string())));
+            continue;
+        }
+
+        outcomes.push(None);
+        outputs.push((
+            index,
+            ImageOutput {
+                options,
+                filter: image_filter(options, options.base.resize, info),
+                path: output,
+            },
+        ));
+    }
+
+    if !outputs.is_empty() {
+        let (indices, outputs): (Vec<usize>, Vec<ImageOutput>) = outputs.into_iter().unzip();
+        let written = encode_image(ffmpeg, input, info, &outputs)?;
+        for (index, output) in indices.into_iter().zip(written) {
+            let options = targets[index];
+            let codec = options.encoder.codec();
+            // Another format's output isn't overwritten by this one's copy of the original
+            let others: Vec<&Path> = paths
+                .iter()
+                .enumerate()
+                .filter(|&(other, _)| other != index)
+                .map(|(_, path)| path.as_path())
+                .collect();
+            let base = options.base.without_copy_over(input, &others)?;
+            outcomes[index] = Some(base.check_gain(ffmpeg, input, info, codec, output)?);
+        }
+    }
 
-    Ok(results)
+    Ok(outcomes.into_iter().flatten().collect())
 }
 
-pub fn compress_image(
+/// Compress a single image into a `--responsive` set per format in `targets`, one
+/// image per width. Each width is written to every format by one ffmpeg run.
+///
+/// `--never-grow` doesn't apply, the smaller widths are the point of the set.
+/// Returns one outcome per target.
+pub fn compress_image_set(
     ffmpeg: &Path,
     input: &Path,
-    options: &ImageCompressOptions,
-) -> Result<PathBuf> {
+    info: &MediaInfo,
+    targets: &[&ImageCompressOptions],
+    responsive: &Responsive,
+) -> Result<Vec<Outcome>> {
     if !ffmpeg.exists() {
         bail!("FFmpeg executable not found at: {}", ffmpeg.display());
     }
 
-    let relative_path = input
-        .strip_prefix(&options.base.input_path)
-        .unwrap_or(input);
+    let ladder = responsive.ladder(info);
+    let mut outputs = Vec::new();
+    let mut sets = Vec::new();
+    let mut up_to_date = Vec::new();
+    for options in targets {
+        let output = options.base.output_file(input)?;
+        let mut variants = Vec::new();
+        let mut fresh = true;
+        for &width in &ladder {
+            let path = options.base.variant_file(&output, width);
+            fresh &= options
+                .base
+                .is_up_to_date(input, options.encoder.codec(), &path)?;
+            variants.push(Variant {
+                path,
+                width,
+                height: Responsive::height_for(info, width),
+            });
+        }
+
+        outputs.push(output);
+        sets.push(variants);
+        up_to_date.push(fresh);
+    }
+
+    if up_to_date.iter().all(|fresh| *fresh) {
+        return Ok(targets
+            .iter()
+            .map(|_| Outcome::Skipped("up to date".to_string()))
+            .collect());
+    }
+
+    for (step, &width) in ladder.iter().enumerate() {
+        let resize = Resize {
+            max_width: Some(width),
+            ..Resize::default()
+        };
+        let outputs: Vec<ImageOutput> = targets
+            .iter()
+            .zip(&sets)
+            .zip(&up_to_date)
+            .filter(|(_, fresh)| !**fresh)
+            .map(|((options, variants), _)| ImageOutput {
+                options,
+                filter: image_filter(options, resize, info),
+                path: variants[step].path.clone(),
+            })
+            .collect();
+        encode_image(ffmpeg, input, info, &outputs)?;
+    }
+    // All formats share the stem, so the first output names the srcset file
+    if let Some(output) = outputs.first() {
+        responsive.write_srcset(input, output, &sets)?;
+    }
 
-    let relative_dir = relative_path.parent().unwrap_or(Path::new(""));
-    let output_dir = options.base.output_path.join(relative_dir);
+    Ok(sets
+        .into_iter()
+        .zip(up_to_date)
+        .map(|(variants, fresh)| {
+            if fresh {
+                Outcome::Skipped("up to date".to_string())
+            } else {
+                Outcome::CompressedSet(variants.into_iter().map(|variant| variant.path).collect())
+            }
+        })
+        .collect())
+}
 
-    //
-    fs::create_dir_all(&output_dir).context("Failed to create output directory")?;
+/// Compress an animated image to a muted video, for `--animated=mp4|webm`. The video
+/// stands in for every format in `targets` and is recorded under the first one.
+///
+/// Returns one outcome per target.
+pub fn compress_animation(
+    ffmpeg: &Path,
+    input: &Path,
+    info: &MediaInfo,
+    targets: &[&ImageCompressOptions],
+    profile: &VideoProfile,
+) -> Result<Vec<Outcome>> {
+    if !ffmpeg.exists() {
+        bail!("FFmpeg executable not found at: {}", ffmpeg.display());
+    }
+    let Some((options, others)) = targets.split_first() else {
+        return Ok(Vec::new());
+    };
 
-    let stem = input
-        .file_stem()
-        .and_then(|s| s.to_str())
-        .unwrap_or("output");
-    let prefix = match &options.base.output_prefix {
-        Some(p) => format!("{}_", p),
-        None => String::new(),
+    let base = &options.base;
+    let output = base.output_file_with_extension(input, profile.container)?;
+    let outcome = if base.is_up_to_date(input, options.encoder.codec(), &output)? {
+        Outcome::Skipped("up to date".to_string())
+    } else {
+        let partial = PartialOutput::new(&output);
+        base.mark_started(input, options.encoder.codec(), partial.path())?;
+
+        let mut args = vec![
+            "-i".to_string(),
+            input.to_str().context("Invalid input path")?.to_string(),
+            "-an".to_string(),
+        ];
+        // yuv420p needs even dimensions, which GIFs often don't have
+        let filter = base
+            .resize
+            .filter(info, true, "bicubic")
+            .unwrap_or_else(|| "scale=trunc(iw/2)*2:trunc(ih/2)*2".to_string());
+        args.extend(["-vf".to_string(), filter]);
+        let (crf, preset) = profile.codec.crf_and_preset(&base.level);
+        args.extend(profile.args(crf, &preset));
+        args.extend(base.metadata.args(info));
+        if matches!(profile.container, "mp4" | "mov") {
+            args.extend(["-movflags".to_string(), "+faststart".to_string()]);
+        }
+        args.extend([
+            "-y".to_string(),
+            partial
+                .path()
+                .to_str()
+                .context("Invalid output path")?
+                .to_string(),
+        ]);
+
+        let result = run_ffmpeg(ffmpeg, &args)?;
+        if !result.status.success() {
+            let stderr = String::from_utf8_lossy(&result.stderr);
+            bail!("Failed to compress {}: {}", input.display(), stderr)
+        }
+        let output = partial.persist()?;
+        base.check_gain(ffmpeg, input, info, options.encoder.codec(), output)?
     };
 
-    let new_filename = format!("{}{}.{}", prefix, stem, options.base.output_extension);
-    let mut output = output_dir.join(new_filename);
+    let mut outcomes = vec![outcome];
+    outcomes.extend(
+        others
+            .iter()
+            .map(|_| Outcome::Skipped(format!("animated, written as {}", profile.container))),
+    );
+    Ok(outcomes)
+}
 
-    // Avoid overwriting output path.
-    if input == output {
-        let new_filename = format!(
-            "compressed_{}{}.{}",
-            prefix, stem, options.base.output_extension
+/// One output of an [`encode_image`] run.
+struct ImageOutput<'a> {
+    options: &'a ImageCompressOptions,
+    /// `-vf` filter applied to this output only
+    filter: Option<String>,
+    path: PathBuf,
+}
+
+/// The `-vf` filter chain of an image output: turning the pixels upright, downscaling to
+/// `resize` (lanczos keeps stills sharper than the default bicubic), then whatever the
+/// metadata policy needs.
+fn image_filter(
+    options: &ImageCompressOptions,
+    resize: Resize,
+    info: &MediaInfo,
+) -> Option<String> {
+    let filters: Vec<String> = [
+        info.orientation
+            .and_then(|orientation| orientation.filter())
+            .map(str::to_string),
+        resize.filter(info, false, "lanczos"),
+        options.base.metadata.filter().map(str::to_string),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+
+    (!filters.is_empty()).then(|| filters.join(","))
+}
+
+/// Encode `input` to every output through temporary files, in a single ffmpeg run.
+/// Returns the written paths, in the order of `outputs`.
+fn encode_image(
+    ffmpeg: &Path,
+    input: &Path,
+    info: &MediaInfo,
+    outputs: &[ImageOutput],
+) -> Result<Vec<PathBuf>> {
+    let mut args = Vec::new();
+    // The orientation is applied by image_filter, ffmpeg mustn't turn the image a second time
+    if info.orientation.is_some_and(|o| o.filter().is_some()) {
+        args.push("-noautorotate".to_string());
+    }
+    args.extend([
+        "-i".to_string(),
+        input.to_str().context("Invalid input path")?.to_string(),
+        "-y".to_string(),
+    ]);
+
+    let mut partials = Vec::new();
+    for output in outputs {
+        let partial = PartialOutput::new(&output.path);
+        output
+            .options
+            .base
+            .mark_started(input, output.options.encoder.codec(), partial.path())?;
+
+        // Options before an output file apply to that output only
+        if let Some(filter) = &output.filter {
+            args.extend(["-vf".to_string(), filter.clone()]);
+        }
+        let encoder = &output.options.encoder;
+        match info
+            .animation
+            .and_then(|animation| encoder.animated_args(animation.plays))
+        {
+            Some(animated) => args.extend(animated),
+            None => args.extend(encoder.args()),
+        }
+        args.extend(output.options.base.metadata.args(info));
+        args.push(
+            partial
+                .path()
+                .to_str()
+                .context("Invalid output path")?
+                .to_string(),
         );
-        output = output_dir.join(new_filename);
-    }
-
-    let result = Command::new(ffmpeg)
-        .args([
-            "-i",
-            input.to_str().context("Invalid input path")?,
-            "-c:v",
-            "libwebp",
-            "-quality",
-            &options.quality.to_string(),
-            "-compression_level",
-            &options.compression_level.to_string(),
-            "-y",
-            output.to_str().context("Invalid output path")?,
-        ])
-        .output()
-        .context("Failed to execute ffmpeg")?;
+        partials.push(partial);
+    }
+
+    let result = run_ffmpeg(ffmpeg, &args)?;
 
     if result.status.success() {
-        Ok(output)
+        partials.into_iter().map(PartialOutput::persist).collect()
     } else {
         let stderr = String::from_utf8_lossy(&result.stderr);
         bail!("Failed to compress {}: {}", input.display(), stderr)
     }
 }
 
+/// Compress `images` to every format in `targets`, one record per image and format.
 pub fn compress_all_images(
     ffmpeg: &Path,
-    images: &Vec<PathBuf>,
-    options: &ImageCompressOptions,
-) -> Result<Vec<Result<PathBuf>>> {
-    // Initialize the progress bar
-    let count = images.len() as u64;
-    let pb = init_progress_bar(count);
-
-    let results: Vec<Result<PathBuf>> = images
-        .par_iter()
-        .map(|image| {
-            // Note: Avoid using println! here as it interferes with the progress bar
-            let name = image.file_name().unwrap_or_default().to_string_lossy();
-            pb.println(format!("Processing: {}", name));
-
-            let start = std::time::Instant::now();
-            let res = compress_image(ffmpeg, image, options);
-            let duration = start.elapsed();
-
-            match &res {
-                Ok(_) => pb.println(format!("Finished: {} (took {:.1?})", name, duration)),
-                Err(e) => {
-                    pb.println(format!("FAILED: {} \nReason: {}", name, e));
-                }
+    images: &[PathBuf],
+    targets: &[ImageCompressOptions],
+) -> Result<Vec<FileRecord>> {
+    // Stills encode in a blink, so only the overall bar is shown
+    let batch = Batch {
+        ffmpeg,
+        kind: MediaKind::Image,
+        targets: targets
+            .iter()
+            .map(|options| Target {
+                base: &options.base,
+                codec: options.encoder.codec(),
+                settings: options.settings(),
+            })
+            .collect(),
+        file_bars: false,
+        finish_message: "Images Compression complete",
+    };
+    let Some(first) = targets.first() else {
+        return Ok(Vec::new());
+    };
+    if targets
+        .iter()
+        .any(|options| options.responsive != first.responsive || options.animated != first.animated)
+    {
+        bail!("Every image format of a run must use the same --responsive and --animated settings");
+    }
+    let responsive = first.responsive.as_ref();
+    let animated = &first.animated;
+
+    Ok(run_batch(images, batch, |image, info, pending, _| {
+        let pending: Vec<&ImageCompressOptions> =
+            pending.iter().map(|&index| &targets[index]).collect();
+        if let Some(animation) = info.animation {
+            if animation.format == AnimatedFormat::WebP {
+                bail!(
+                    "{} is an animated WebP, ffmpeg's webp decoder only reads stills before ffmpeg 8.0",
+                    image.display()
+                );
             }
-
-            res
-        })
-        .progress_with(pb.clone()) // Attach the progress bar to rayon iterator
-        .collect();
-
-    pb.finish_with_message("Images Compression complete");
-
-    Ok(results)
+            if let AnimatedOutput::Video(profile) = animated {
+                return compress_animation(ffmpeg, image, info, &pending, profile);
+            }
+        }
+        match responsive {
+            Some(responsive) => compress_image_set(ffmpeg, image, info, &pending, responsive),
+            None => compress_image(ffmpeg, image, info, &pending),
+        }
+    }))
 }
 
+/// Compress a single video file, reporting encode progress on `bar`.
 pub fn compress_video(
     ffmpeg: &Path,
     input: &Path,
+    info: &MediaInfo,
     options: &VideoCompressOptions,
-) -> Result<PathBuf> {
+    bar: &ProgressBar,
+) -> Result<Outcome> {
     if !ffmpeg.exists() {
         bail!("FFmpeg executable not found at: {}", ffmpeg.display());
     }
 
-    let relative_path = input
-        .strip_prefix(&options.base.input_path)
-        .unwrap_or(input);
-    let relative_dir = relative_path.parent().unwrap_or(Path::new(""));
-    let output_dir = options.base.output_path.join(relative_dir);
-    //
-    fs::create_dir_all(&output_dir).context("Failed to create output directory")?;
-
-    let prefix = match &options.base.output_prefix {
-        Some(p) => format!("{}_", p),
-        None => String::new(),
+    let output = options.base.output_file(input)?;
+    let codec = options.profile.codec.encoder();
+    if options.base.is_up_to_date(input, codec, &output)? {
+        return Ok(Outcome::Skipped("up to date".to_string()));
+    }
+    // An optimal input that's too big for --target-size still has to be encoded
+    let fits = match options.target_size {
+        Some(target_size) => fs::metadata(input)?.len() <= target_size.bytes(),
+        None => true,
     };
+    if fits && options.is_already_optimal(info) {
+        if !options.base.has_output_extension(input) {
+            // Only the container is wrong, e.g. VP9 and Opus in .mkv for --videos=webm
+            let container_args = options.container_args();
+            let output =
+                options
+                    .base
+                    .remux(ffmpeg, input, info, codec, &output, &container_args)?;
+            return Ok(Outcome::Copied(output, "already optimal".to_string()));
+        }
+        return options
+            .base
+            .keep_original(ffmpeg, input, info, codec, "already optimal");
+    }
+    let partial = PartialOutput::new(&output);
+    options.base.mark_started(input, codec, partial.path())?;
 
-    let stem = input
-        .file_stem()
-        .and_then(|s| s.to_str())
-        .unwrap_or("output");
+    let output_str = partial.path().to_str().context("Invalid output path")?;
 
-    let new_filename = format!("{}{}.{}", prefix, stem, options.base.output_extension);
-    let mut output = output_dir.join(new_filename);
+    let mut output_args = options.base.metadata.args(info);
+    output_args.extend(options.container_args());
 
-    // Avoid overwriting output path
-    if input == output {
-        let new_filename = format!(
-            "compressed_{}{}.{}",
-            prefix, stem, options.base.output_extension
-        );
-        output = output_dir.join(new_filename);
+    output_args.extend([
+        "-c:a".to_string(),
+        options.profile.audio_codec.to_string(),
+        "-b:a".to_string(),
+        options.audio_bitrate.clone(),
+        "-y".to_string(), // Overwrite output
+        output_str.to_string(),
+    ]);
+
+    if let Some(target_size) = options.target_size {
+        encode_to_size(ffmpeg, input, info, options, target_size, &output_args, bar)?;
+        let output = partial.persist()?;
+        return options.base.check_gain(ffmpeg, input, info, codec, output);
     }
 
-    //  Ensure input path is valid
-    let input_str = input.to_str().context("Invalid input path")?;
-    let output_str = output.to_str().context("Invalid output path")?;
-
-    let result = Command::new(ffmpeg)
-        .args([
-            "-i",
-            input_str,
-            // Video Codec
-            "-c:v",
-            &options.video_codec,
-            // CRITICAL: Force pixel format for Chrome/Web compatibility
-            "-pix_fmt",
-            "yuv420p",
-            // CRITICAL: VP9 requires -b:v 0 for CRF to work
-            "-b:v",
-            "0",
-            "-crf",
-            &options.crf.to_string(),
-            // Performance settings (makes encoding faster than default)
-            "-deadline",
-            &options.preset,
-            "-cpu-used",
-            "4", // Range 0-5. 4 is a good balance of speed/size
-            "-row-mt",
-            "1", // Enable row-based multithreading
-            // Audio Codec
-            "-c:a",
-            "libopus",
-            "-b:a",
-            "64k",
-            "-y", // Overwrite output
-            output_str,
-        ])
-        .output()
-        .context("Failed to execute ffmpeg process")?;
+    let mut crf_args = video_input_args(input, info, options)?;
+    crf_args.extend(options.profile.args(options.crf, &options.preset));
+    crf_args.extend(output_args);
+    let result = run_ffmpeg_with_progress(ffmpeg, &crf_args, info.duration, bar)?;
 
     if result.status.success() {
-        Ok(output)
+        let output = partial.persist()?;
+        options.base.check_gain(ffmpeg, input, info, codec, output)
     } else {
         let stderr = String::from_utf8_lossy(&result.stderr);
         bail!("Failed to compress {}: {}", input.display(), stderr)
     }
 }
 
-pub fn compress_all_videos(
+/// How many times the final pass of a `--target-size` encode is run before giving up.
+const MAX_SIZE_ATTEMPTS: usize = 3;
+
+/// The options before the video encoder's: the input and the scaling.
+fn video_input_args(
+    input: &Path,
+    info: &MediaInfo,
+    options: &VideoCompressOptions,
+) -> Result<Vec<String>> {
+    //  Ensure input path is valid
+    let input_str = input.to_str().context("Invalid input path")?;
+
+    let mut args = vec!["-i".to_string(), input_str.to_string()];
+    // yuv420p needs even dimensions
+    if let Some(filter) = options.base.resize.filter(info, true, "bicubic") {
+        args.extend(["-vf".to_string(), filter]);
+    }
+
+    Ok(args)
+}
+
+/// Encode `input` to the bitrate that fits `target_size`, in two passes where the
+/// encoder supports them. `output_args` follow the video encoder options, ending with
+/// the output path.
+///
+/// Encoders only hit the average bitrate approximately, so when the file comes out
+/// too big the final pass is run again at a bitrate scaled down by the overshoot.
+fn encode_to_size(
     ffmpeg: &Path,
-    videos: &Vec<PathBuf>,
+    input: &Path,
+    info: &MediaInfo,
     options: &VideoCompressOptions,
-) -> Result<Vec<Result<PathBuf>>> {
-    let count = videos.len() as u64;
-    println!("Found {} videos to compress", count);
+    target_size: TargetSize,
+    output_args: &[String],
+    bar: &ProgressBar,
+) -> Result<()> {
+    let Some(duration) = info.duration else {
+        bail!(
+            "Can't fit {} to --target-size, ffmpeg couldn't read its duration",
+            input.display()
+        );
+    };
+    let audio_bitrate = match info.audio_codec {
+        Some(_) => parse_bitrate(&options.audio_bitrate).unwrap_or(0),
+        None => 0,
+    };
+    let mut bitrate = target_size.video_bitrate(duration, audio_bitrate)?;
+    let codec = options.profile.codec;
+    // A single pass misses the size by too much to be worth running
+    if !codec.supports_two_pass() {
+        bail!(
+            "--target-size needs a two-pass encode, which {} can't run",
+            codec.encoder()
+        );
+    }
+    let input_args = video_input_args(input, info, options)?;
+    let output = Path::new(output_args.last().context("Missing output path")?);
+
+    // The pass statistics go to a directory of their own, so parallel jobs (and runs)
+    // never read each other's
+    let passlog_dir = tempfile::Builder::new()
+        .prefix("crunch-passlog-")
+        .tempdir()
+        .context("Failed to create a directory for the pass statistics")?;
+    let passlog = passlog_dir.path().join("pass");
+    let passlog = passlog.to_str().context("Invalid temporary path")?;
+
+    let run = |args: Vec<String>, pass: &str| -> Result<()> {
+        let result = run_ffmpeg_with_progress(ffmpeg, &args, info.duration, bar)?;
+        if !result.status.success() {
+            let stderr = String::from_utf8_lossy(&result.stderr);
+            bail!(
+                "Failed to compress {} ({}): {}",
+                input.display(),
+                pass,
+                stderr
+            )
+        }
+        Ok(())
+    };
+
+    // The first pass only analyses the video, nothing is written
+    let mut args = input_args.to_vec();
+    args.extend(
+        options
+            .profile
+            .bitrate_args(bitrate, &options.preset, Some((1, passlog))),
+    );
+    args.extend(["-an", "-f", "null", "-"].map(str::to_string));
+    run(args, "first pass")?;
+
+    for attempt in 1..=MAX_SIZE_ATTEMPTS {
+        // The first pass's statistics still hold at a different bitrate
+        let mut args = input_args.to_vec();
+        args.extend(
+            options
+                .profile
+                .bitrate_args(bitrate, &options.preset, Some((2, passlog))),
+        );
+        args.extend(output_args.iter().cloned());
+        run(args, &format!("{} kb/s", bitrate / 1000))?;
+
+        let size = fs::metadata(output)?.len();
+        if size <= target_size.bytes() {
+            return Ok(());
+        }
+        match target_size.retry_bitrate(bitrate, size) {
+            Some(lower) if attempt < MAX_SIZE_ATTEMPTS => bitrate = lower,
+            _ => bail!(
+                "{} is still {} at {} kb/s, over the --target-size of {}",
+                input.display(),
+                crate::summary::format_size(size),
+                bitrate / 1000,
+                crate::summary::format_size(target_size.bytes())
+            ),
+        }
+    }
+
+    unreachable!("the last attempt returns or bails")
+}
 
-    if count == 0 {
+pub fn compress_all_videos(
+    ffmpeg: &Path,
+    videos: &[PathBuf],
+    options: &VideoCompressOptions,
+) -> Result<Vec<FileRecord>> {
+    if videos.is_empty() {
         return Ok(vec![]);
     }
 
@@ -509,63 +1357,385 @@ pub fn compress_all_videos(
         bail!("FFmpeg executable not found at: {}", ffmpeg.display());
     }
 
-    let pb = init_progress_bar(count);
-
     // 2. Process videos in parallel
-    let results: Vec<Result<PathBuf>> = videos
-        .par_iter()
-        .map(|video| {
-            let name = video.file_name().unwrap_or_default().to_string_lossy();
-            pb.println(format!("Processing: {}", name));
-
-            let start = std::time::Instant::now();
-            let res = compress_video(ffmpeg, video, options);
-            let duration = start.elapsed();
-
-            match &res {
-                Ok(_) => {
-                    pb.println(format!("Finished: {} (took {:.1?})", name, duration));
-                }
-                Err(e) => {
-                    // Print the actual error to the console so you can see it
-                    pb.println(format!("FAILED: {} \nReason: {}", name, e));
-                }
-            }
-            res
-        })
-        .progress_with(pb.clone())
-        .collect();
-
-    pb.finish_with_message("Video compression complete");
+    let batch = Batch {
+        ffmpeg,
+        kind: MediaKind::Video,
+        targets: vec![Target {
+            base: &options.base,
+            codec: options.profile.codec.encoder(),
+            settings: options.settings(),
+        }],
+        file_bars: true,
+        finish_message: "Video compression complete",
+    };
 
-    // 3. Check if everything failed
-    let failures = results.iter().filter(|r| r.is_err()).count();
-    if failures > 0 {
-        println!(
-            "\nWARNING: {} videos failed to compress. Check the logs above.",
-            failures
-        );
-    }
-    Ok(results)
+    Ok(run_batch(videos, batch, |video, info, _, bar| {
+        compress_video(ffmpeg, video, info, options, bar).map(|outcome| vec![outcome])
+    }))
 }
 
 #[allow(unused)]
 pub fn compress_all_with_progress(
     ffmpeg: &Path,
-    images: &Vec<PathBuf>,
+    images: &[PathBuf],
     options: &ImageCompressOptions,
 ) -> Result<(Vec<PathBuf>, Vec<anyhow::Error>)> {
-    let results = compress_all_images(ffmpeg, images, options)?;
+    let results = compress_all_images(ffmpeg, images, std::slice::from_ref(options))?;
 
     let mut successes = Vec::new();
     let mut failures = Vec::new();
 
-    for result in results {
-        match result {
-            Ok(path) => successes.push(path),
-            Err(e) => failures.push(e),
+    for record in results {
+        if let Some(error) = record.error() {
+            failures.push(anyhow::anyhow!(error.to_string()));
+        } else if let Some(path) = record.output {
+            successes.push(path);
         }
     }
 
     Ok((successes, failures))
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ffmpeg::test_util::{exif, jpeg_segment};
+    use crate::summary::FileStatus;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    fn base(temp_dir: &TempDir, never_grow: Option<NoGainAction>) -> BaseCompressOptions {
+        BaseCompressOptions {
+            input_path: temp_dir.path().join("in"),
+            output_path: temp_dir.path().join("out"),
+            never_grow,
+            min_saving: 10,
+            ..BaseCompressOptions::new_with("image")
+        }
+    }
+
+    fn write(path: &Path, len: usize) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, vec![0u8; len]).unwrap();
+    }
+
+    #[test]
+    fn test_output_file_keeps_relative_dir() {
+        let temp_dir = TempDir::new().unwrap();
+        let base = base(&temp_dir, None);
+
+        let output = base
+            .output_file(&temp_dir.path().join("in/a/b/photo.jpg"))
+            .unwrap();
+
+        assert_eq!(output, temp_dir.path().join("out/a/b/photo.webp"));
+        assert!(temp_dir.path().join("out/a/b").is_dir());
+    }
+
+    #[test]
+    fn test_is_up_to_date() {
+        let temp_dir = TempDir::new().unwrap();
+        let input = temp_dir.path().join("in/photo.jpg");
+        let output = temp_dir.path().join("out/photo.webp");
+        write(&input, 1000);
+
+        let mut base = base(&temp_dir, None);
+        assert!(!base.is_up_to_date(&input, "libwebp", &output).unwrap());
+
+        // Only checked in incremental mode
+        write(&output, 500);
+        assert!(!base.is_up_to_date(&input, "libwebp", &output).unwrap());
+        base.incremental = true;
+        assert!(base.is_up_to_date(&input, "libwebp", &output).unwrap());
+
+        // The input changed after the output was written
+        let later = fs::metadata(&output).unwrap().modified().unwrap() + Duration::from_secs(5);
+        fs::File::options()
+            .write(true)
+            .open(&input)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        assert!(!base.is_up_to_date(&input, "libwebp", &output).unwrap());
+    }
+
+    #[test]
+    fn test_is_up_to_date_copy() {
+        let temp_dir = TempDir::new().unwrap();
+        let input = temp_dir.path().join("in/photo.jpg");
+        let output = temp_dir.path().join("out/photo.webp");
+        let copy = temp_dir.path().join("out/photo.jpg");
+        write(&input, 1000);
+        write(&copy, 1000);
+
+        let manifest = Arc::new(Manifest::open(&temp_dir.path().join("out"), false).unwrap());
+        let base = BaseCompressOptions {
+            incremental: true,
+            manifest: Some(manifest.clone()),
+            ..base(&temp_dir, Some(NoGainAction::Copy))
+        };
+        // A file with the input's name isn't enough, e.g. the output of --images=jpg
+        assert!(!base.is_up_to_date(&input, "libwebp", &output).unwrap());
+
+        manifest
+            .finish(&FileRecord {
+                output: Some(copy.clone()),
+                ..FileRecord::for_test(
+                    MediaKind::Image,
+                    &input,
+                    "libwebp",
+                    FileStatus::Copied("no gain".to_string()),
+                )
+            })
+            .unwrap();
+        assert!(base.is_up_to_date(&input, "libwebp", &output).unwrap());
+        assert!(!base.is_up_to_date(&input, "mjpeg", &output).unwrap());
+    }
+
+    #[test]
+    fn test_check_gain() {
+        let temp_dir = TempDir::new().unwrap();
+        let input = temp_dir.path().join("in/photo.jpg");
+        let output = temp_dir.path().join("out/photo.webp");
+        write(&input, 1000);
+        let check_gain = |base: BaseCompressOptions, output: PathBuf| {
+            let info = MediaInfo::default();
+            base.check_gain(Path::new("ffmpeg"), &input, &info, "libwebp", output)
+        };
+
+        // Without never_grow every output is kept
+        write(&output, 2000);
+        let outcome = check_gain(base(&temp_dir, None), output.clone());
+        assert_eq!(outcome.unwrap(), Outcome::Compressed(output.clone()));
+
+        // 5% smaller doesn't reach min_saving = 10
+        write(&output, 950);
+        let outcome = check_gain(base(&temp_dir, Some(NoGainAction::Skip)), output.clone());
+        assert_eq!(outcome.unwrap(), Outcome::Skipped("no gain".to_string()));
+        assert!(!output.exists());
+
+        write(&output, 2000);
+        // Copied as it is, see test_copy_original_drops_metadata for the other policies
+        let copy = BaseCompressOptions {
+            metadata: MetadataPolicy::Keep,
+            ..base(&temp_dir, Some(NoGainAction::Copy))
+        };
+        let outcome = check_gain(copy, output.clone());
+        let copy = temp_dir.path().join("out/photo.jpg");
+        assert_eq!(
+            outcome.unwrap(),
+            Outcome::Copied(copy.clone(), "no gain".to_string())
+        );
+        assert_eq!(fs::metadata(copy).unwrap().len(), 1000);
+
+        write(&output, 500);
+        let outcome = check_gain(base(&temp_dir, Some(NoGainAction::Skip)), output.clone());
+        assert_eq!(outcome.unwrap(), Outcome::Compressed(output));
+    }
+
+    #[test]
+    fn test_video_already_optimal() {
+        let options =
+            VideoCompressOptions::with_base(BaseCompressOptions::new_with("video")).unwrap();
+        let info = MediaInfo {
+            video_codec: Some("vp9".to_string()),
+            width: Some(1280),
+            height: Some(720),
+            fps: Some(30.0),
+            video_bitrate: Some(800_000),
+            audio_codec: Some("opus".to_string()),
+            audio_bitrate: Some(64_000),
+            ..MediaInfo::default()
+        };
+        assert!(options.is_already_optimal(&info));
+
+        // Above what crf 33 typically gives at 720p
+        let heavy = MediaInfo {
+            video_bitrate: Some(8_000_000),
+            ..info.clone()
+        };
+        assert!(!options.is_already_optimal(&heavy));
+
+        // Right video, but the audio still needs encoding
+        let aac = MediaInfo {
+            audio_codec: Some("aac".to_string()),
+            ..info.clone()
+        };
+        assert!(!options.is_already_optimal(&aac));
+
+        let h264 = MediaInfo {
+            video_codec: Some("h264".to_string()),
+            ..info
+        };
+        assert!(!options.is_already_optimal(&h264));
+    }
+
+    #[test]
+    fn test_optimal_in_other_container() {
+        let mut base = BaseCompressOptions::new_with("video");
+        base.output_extension = "mp4".to_string();
+        let options = VideoCompressOptions::with_base(base).unwrap();
+        let info = MediaInfo {
+            video_codec: Some("h264".to_string()),
+            width: Some(1280),
+            height: Some(720),
+            video_bitrate: Some(800_000),
+            ..MediaInfo::default()
+        };
+        assert!(options.is_already_optimal(&info));
+
+        // The streams are right, but a .mkv can't be kept as the .mp4 output
+        assert!(options.base.has_output_extension(Path::new("in/clip.MP4")));
+        assert!(!options.base.has_output_extension(Path::new("in/clip.mkv")));
+
+        // So they are copied into an mp4, without re-encoding
+        let args = options
+            .base
+            .remux_args(
+                Path::new("in/clip.mkv"),
+                &info,
+                &options.container_args(),
+                Path::new("out/clip.mp4"),
+            )
+            .unwrap();
+        let args = args.join(" ");
+        assert!(args.starts_with("-i in/clip.mkv -c copy "));
+        assert!(args.contains("-movflags +faststart"));
+        assert!(args.ends_with("-y out/clip.mp4"));
+    }
+
+    #[test]
+    fn test_audio_already_optimal() {
+        let options =
+            AudioCompressOptions::with_base(BaseCompressOptions::new_with("audio")).unwrap();
+        let info = MediaInfo {
+            audio_codec: Some("mp3".to_string()),
+            audio_bitrate: Some(96_000),
+            sample_rate: Some(44100),
+            channels: Some(2),
+            ..MediaInfo::default()
+        };
+        assert!(options.is_already_optimal(&info));
+
+        let high_bitrate = MediaInfo {
+            audio_bitrate: Some(320_000),
+            ..info.clone()
+        };
+        assert!(!options.is_already_optimal(&high_bitrate));
+
+        let unknown_bitrate = MediaInfo {
+            audio_bitrate: None,
+            ..info.clone()
+        };
+        assert!(!options.is_already_optimal(&unknown_bitrate));
+
+        let wav = MediaInfo {
+            audio_codec: Some("pcm_s16le".to_string()),
+            ..info
+        };
+        assert!(!options.is_already_optimal(&wav));
+    }
+
+    #[test]
+    fn test_image_targets_share_animated_and_responsive() {
+        let webp = ImageCompressOptions::default();
+        let avif = ImageCompressOptions {
+            animated: AnimatedOutput::for_format("mp4").unwrap(),
+            ..ImageCompressOptions::with_base(BaseCompressOptions {
+                output_extension: "avif".to_string(),
+                ..BaseCompressOptions::new_with("image")
+            })
+            .unwrap()
+        };
+
+        let error = compress_all_images(Path::new("ffmpeg"), &[], &[webp, avif]).unwrap_err();
+        assert!(error.to_string().contains("--animated"));
+    }
+
+    #[test]
+    fn test_without_copy_over() {
+        let temp_dir = TempDir::new().unwrap();
+        let input = temp_dir.path().join("in/photo.jpg");
+        let jpg = temp_dir.path().join("out/photo.jpg");
+        let png = temp_dir.path().join("out/photo.png");
+
+        let copy = base(&temp_dir, Some(NoGainAction::Copy));
+        let base = copy.without_copy_over(&input, &[&png]).unwrap();
+        assert_eq!(base.never_grow, Some(NoGainAction::Copy));
+        // The copy would overwrite the output of --images=jpg
+        let base = copy.without_copy_over(&input, &[&png, &jpg]).unwrap();
+        assert_eq!(base.never_grow, Some(NoGainAction::Skip));
+    }
+
+    #[test]
+    fn test_keep_original() {
+        let temp_dir = TempDir::new().unwrap();
+        let input = temp_dir.path().join("in/clip.webm");
+        write(&input, 1000);
+        let keep_original = |base: BaseCompressOptions| {
+            let info = MediaInfo::default();
+            base.keep_original(
+                Path::new("ffmpeg"),
+                &input,
+                &info,
+                "libvpx-vp9",
+                "already optimal",
+            )
+        };
+
+        assert!(matches!(
+            keep_original(base(&temp_dir, None)).unwrap(),
+            Outcome::Skipped(reason) if reason == "already optimal"
+        ));
+
+        let copy = BaseCompressOptions {
+            metadata: MetadataPolicy::Keep,
+            ..base(&temp_dir, Some(NoGainAction::Copy))
+        };
+        let Outcome::Copied(output, reason) = keep_original(copy).unwrap() else {
+            panic!("expected the original to be copied");
+        };
+        assert_eq!(output, temp_dir.path().join("out/clip.webm"));
+        assert_eq!(reason, "already optimal");
+        assert_eq!(fs::metadata(&output).unwrap().len(), 1000);
+    }
+
+    #[test]
+    fn test_copy_original_drops_metadata() {
+        let temp_dir = TempDir::new().unwrap();
+        let copy = base(&temp_dir, Some(NoGainAction::Copy));
+        let copy_original = |input: &Path| {
+            let info = MediaInfo::default();
+            copy.copy_original(Path::new("ffmpeg"), input, &info, "libwebp", "no gain")
+        };
+
+        // A JPEG with the camera make in its EXIF segment
+        let mut jpeg = vec![0xFF, 0xD8];
+        jpeg.extend(jpeg_segment(0xE1, &exif(1, true)));
+        jpeg.extend([0xFF, 0xDA, 0x00, 0x02, 0xFF, 0xD9]);
+        let input = temp_dir.path().join("in/photo.jpg");
+        fs::create_dir_all(input.parent().unwrap()).unwrap();
+        fs::write(&input, &jpeg).unwrap();
+
+        let Outcome::Copied(output, _) = copy_original(&input).unwrap() else {
+            panic!("expected the original to be copied");
+        };
+        assert_eq!(output, temp_dir.path().join("out/photo.jpg"));
+        assert_eq!(
+            fs::read(&output).unwrap(),
+            [0xFF, 0xD8, 0xFF, 0xDA, 0x00, 0x02, 0xFF, 0xD9]
+        );
+
+        // Nothing crunch can rewrite, so it isn't copied at all
+        let input = temp_dir.path().join("in/scan.tiff");
+        write(&input, 1000);
+        assert_eq!(
+            copy_original(&input).unwrap(),
+            Outcome::Skipped(
+                "no gain, and the original can't be copied without its metadata".to_string()
+            )
+        );
+        assert!(!temp_dir.path().join("out/scan.tiff").exists());
+    }
+}
@@ -0,0 +1,100 @@
+use super::cache::cache_dir;
+use crate::consts::FFMPEG_BINARY;
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// SHA-256 of [`FFMPEG_BINARY`], computed by build.rs.
+const FFMPEG_SHA256: &str = env!("CRUNCH_FFMPEG_SHA256");
+
+/// Extract the embedded ffmpeg to the cache once and reuse it afterwards.
+///
+/// Each build gets its own directory keyed by the hash of its binary, so upgrading
+/// crunch never runs a stale ffmpeg. A cached copy is hashed again before use and
+/// rewritten if it doesn't match, and a lock file keeps concurrent crunch processes
+/// from writing it at the same time.
+pub fn extract(file_name: &str) -> Result<PathBuf> {
+    let hash = FFMPEG_SHA256;
+    let dir = cache_dir()?.join(format!("ffmpeg-{}", &hash[..16]));
+    fs::create_dir_all(&dir)
+        .with_context(|| format!("Failed to create cache directory: {}", dir.display()))?;
+
+    let lock = File::create(dir.join(".lock")).context("Failed to create cache lock file")?;
+    lock.lock().context("Failed to lock the ffmpeg cache")?;
+
+    let ffmpeg_path = dir.join(file_name);
+    if !is_valid(&ffmpeg_path, hash) {
+        write_binary(&ffmpeg_path, FFMPEG_BINARY)
+            .with_context(|| format!("Failed to extract ffmpeg to {}", ffmpeg_path.display()))?;
+    }
+
+    // Dropping the file releases the lock
+    drop(lock);
+
+    Ok(ffmpeg_path)
+}
+
+/// Whether `path` holds the binary with the given hash.
+fn is_valid(path: &Path, hash: &str) -> bool {
+    let Ok(mut file) = File::open(path) else {
+        return false;
+    };
+
+    let mut hasher = Sha256::new();
+    if io::copy(&mut file, &mut hasher).is_err() {
+        return false;
+    }
+
+    format!("{:x}", hasher.finalize()) == hash
+}
+
+/// Write to a temporary sibling and rename it into place, so a crash mid-write
+/// doesn't leave a truncated binary behind.
+fn write_binary(path: &Path, bytes: &[u8]) -> Result<()> {
+    let tmp = path.with_extension(format!("tmp-{}", process::id()));
+
+    let mut file = File::create(&tmp)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    drop(file);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&tmp, fs::Permissions::from_mode(0o755))?;
+    }
+
+    fs::rename(&tmp, path).inspect_err(|_| {
+        let _ = fs::remove_file(&tmp);
+    })?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_write_and_verify_binary() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("ffmpeg");
+        let bytes = b"not really ffmpeg";
+        let hash = format!("{:x}", Sha256::digest(bytes));
+
+        assert!(!is_valid(&path, &hash));
+
+        write_binary(&path, bytes).unwrap();
+        assert!(is_valid(&path, &hash));
+        // Only the binary is left, no temporary file
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 1);
+
+        // A corrupted copy is caught
+        fs::write(&path, b"not really ffmpeg, truncat").unwrap();
+        assert!(!is_valid(&path, &hash));
+    }
+}
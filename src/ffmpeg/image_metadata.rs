@@ -0,0 +1,226 @@
+use super::animation::webp_chunk_size;
+use super::metadata::MetadataPolicy;
+use super::orientation::Orientation;
+
+/// `data` with the metadata `policy` drops removed, the image data untouched, or `None`
+/// when the format isn't one that can be rewritten this way.
+///
+/// This is how an original is copied through under `safe` and `strip`: re-encoding would
+/// drop the metadata too, but the point of copying the original is not to re-encode it.
+/// JPEG, PNG and WebP are rewritten; everything else needs ffmpeg.
+pub fn strip(data: &[u8], policy: MetadataPolicy) -> Option<Vec<u8>> {
+    if data.starts_with(&[0xFF, 0xD8]) {
+        strip_jpeg(data, policy)
+    } else if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        strip_png(data, policy)
+    } else if data.starts_with(b"RIFF") && data.get(8..12) == Some(b"WEBP") {
+        strip_webp(data, policy)
+    } else {
+        None
+    }
+}
+
+/// Keep the segments needed to decode the image (and the ICC profile under `safe`), drop
+/// EXIF, XMP, IPTC and comments. A rotated photo gets a new EXIF segment holding only its
+/// orientation, so it still displays upright.
+fn strip_jpeg(data: &[u8], policy: MetadataPolicy) -> Option<Vec<u8>> {
+    let mut output = vec![0xFF, 0xD8];
+    let mut exif = orientation_segment(data);
+    let mut pos = 2;
+    loop {
+        if *data.get(pos)? != 0xFF {
+            return None;
+        }
+        let marker = *data.get(pos + 1)?;
+        // Right after SOI, or after JFIF's APP0 which has to come first
+        if marker != 0xE0
+            && let Some(exif) = exif.take()
+        {
+            output.extend(exif);
+        }
+        // Start of scan: everything from here on is image data
+        if marker == 0xDA {
+            output.extend(&data[pos..]);
+            break;
+        }
+        let length = u16::from_be_bytes([*data.get(pos + 2)?, *data.get(pos + 3)?]) as usize;
+        if length < 2 {
+            return None;
+        }
+        let segment = data.get(pos..pos + 2 + length)?;
+
+        let keep = match marker {
+            // JFIF and Adobe (which says how the colors were transformed)
+            0xE0 | 0xEE => true,
+            0xE2 => policy == MetadataPolicy::Safe && segment[4..].starts_with(b"ICC_PROFILE\0"),
+            // Every other application segment and comments
+            0xE1..=0xEF | 0xFE => false,
+            _ => true,
+        };
+        if keep {
+            output.extend(segment);
+        }
+        pos += 2 + length;
+    }
+
+    Some(output)
+}
+
+/// An APP1 segment with just the orientation of `jpeg`, if it isn't upright.
+fn orientation_segment(jpeg: &[u8]) -> Option<Vec<u8>> {
+    let orientation = Orientation::parse(jpeg).filter(|o| !o.is_upright())?;
+    let exif = orientation.exif();
+    let mut segment = vec![0xFF, 0xE1];
+    segment.extend(((exif.len() + 2) as u16).to_be_bytes());
+    segment.extend(exif);
+    Some(segment)
+}
+
+/// Drop text, EXIF and timestamp chunks, and the ICC profile under `strip`.
+fn strip_png(data: &[u8], policy: MetadataPolicy) -> Option<Vec<u8>> {
+    let mut output = data[..8].to_vec();
+    let mut pos = 8;
+    while pos < data.len() {
+        let length = u32::from_be_bytes(data.get(pos..pos + 4)?.try_into().ok()?) as usize;
+        // Length, type, data and CRC
+        let chunk = data.get(pos..pos + 12 + length)?;
+        let keep = match &chunk[4..8] {
+            b"tEXt" | b"zTXt" | b"iTXt" | b"eXIf" | b"tIME" => false,
+            b"iCCP" => policy == MetadataPolicy::Safe,
+            _ => true,
+        };
+        if keep {
+            output.extend(chunk);
+        }
+        pos += 12 + length;
+    }
+
+    Some(output)
+}
+
+/// Drop the EXIF and XMP chunks, and the ICC profile under `strip`, then fix up the flags
+/// of the extended header and the size of the file.
+fn strip_webp(data: &[u8], policy: MetadataPolicy) -> Option<Vec<u8>> {
+    // Flags in the first byte of the VP8X chunk
+    const ICC: u8 = 0x20;
+    const EXIF: u8 = 0x08;
+    const XMP: u8 = 0x04;
+
+    let mut output = data[..12].to_vec();
+    let mut dropped = EXIF | XMP;
+    if policy == MetadataPolicy::Strip {
+        dropped |= ICC;
+    }
+
+    let mut pos = 12;
+    while pos < data.len() {
+        let size = u32::from_le_bytes(data.get(pos + 4..pos + 8)?.try_into().ok()?) as usize;
+        let chunk = data.get(pos..(pos + webp_chunk_size(size)).min(data.len()))?;
+        let keep = match &chunk[..4] {
+            b"EXIF" | b"XMP " => false,
+            b"ICCP" => policy == MetadataPolicy::Safe,
+            _ => true,
+        };
+        if keep {
+            let start = output.len();
+            output.extend(chunk);
+            if &chunk[..4] == b"VP8X" {
+                *output.get_mut(start + 8)? &= !dropped;
+            }
+        }
+        pos += chunk.len();
+    }
+
+    let riff_size = u32::try_from(output.len() - 8).ok()?;
+    output[4..8].copy_from_slice(&riff_size.to_le_bytes());
+    Some(output)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ffmpeg::test_util::{exif, jpeg_segment, png_chunk};
+
+    #[test]
+    fn test_strip_jpeg() {
+        let jfif = jpeg_segment(0xE0, b"JFIF\0\x01\x01\0\0\x01\0\x01\0\0");
+        let icc = jpeg_segment(0xE2, b"ICC_PROFILE\0\x01\x01profile");
+        let quant = jpeg_segment(0xDB, &[0; 65]);
+        let scan = [0xFF, 0xDA, 0x00, 0x02, 0x12, 0x34, 0xFF, 0xD9];
+
+        let mut jpeg = vec![0xFF, 0xD8];
+        jpeg.extend(&jfif);
+        jpeg.extend(jpeg_segment(0xE1, &exif(6, true)));
+        jpeg.extend(jpeg_segment(0xE1, b"http://ns.adobe.com/xap/1.0/\0<gps/>"));
+        jpeg.extend(&icc);
+        jpeg.extend(jpeg_segment(0xFE, b"shot in Amsterdam"));
+        jpeg.extend(&quant);
+        jpeg.extend(scan);
+
+        let safe = strip(&jpeg, MetadataPolicy::Safe).unwrap();
+        // The orientation survives on its own, the image data is untouched
+        let orientation = orientation_segment(&jpeg).unwrap();
+        let expected = [&[0xFF, 0xD8][..], &jfif, &orientation, &icc, &quant, &scan].concat();
+        assert_eq!(safe, expected);
+        assert_eq!(Orientation::parse(&safe), Orientation::parse(&jpeg));
+
+        let stripped = strip(&jpeg, MetadataPolicy::Strip).unwrap();
+        let expected = [&[0xFF, 0xD8][..], &jfif, &orientation, &quant, &scan].concat();
+        assert_eq!(stripped, expected);
+    }
+
+    #[test]
+    fn test_strip_png() {
+        let header = png_chunk(b"IHDR", &[0; 13]);
+        let icc = png_chunk(b"iCCP", b"sRGB\0\0profile");
+        let image = png_chunk(b"IDAT", &[1, 2, 3]);
+        let end = png_chunk(b"IEND", &[]);
+
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        png.extend(&header);
+        png.extend(&icc);
+        png.extend(png_chunk(b"tEXt", b"Author\0Jane"));
+        png.extend(png_chunk(b"eXIf", b"MM\0\x2a"));
+        png.extend(&image);
+        png.extend(&end);
+
+        let signature = &b"\x89PNG\r\n\x1a\n"[..];
+        assert_eq!(
+            strip(&png, MetadataPolicy::Safe).unwrap(),
+            [signature, &header, &icc, &image, &end].concat()
+        );
+        assert_eq!(
+            strip(&png, MetadataPolicy::Strip).unwrap(),
+            [signature, &header, &image, &end].concat()
+        );
+    }
+
+    #[test]
+    fn test_strip_webp() {
+        let mut webp = b"RIFF\0\0\0\0WEBP".to_vec();
+        // ICC, EXIF and XMP flags set, then a 1x1 canvas
+        webp.extend(b"VP8X\x0a\0\0\0\x2c\0\0\0\0\0\0\0\0\0");
+        webp.extend(b"ICCP\x03\0\0\0icc\0");
+        webp.extend(b"VP8L\x02\0\0\0\x2f\0");
+        webp.extend(b"EXIF\x04\0\0\0MM\0\x2a");
+        webp.extend(b"XMP \x05\0\0\0<gps>\0");
+        let size = (webp.len() - 8) as u32;
+        webp[4..8].copy_from_slice(&size.to_le_bytes());
+
+        let safe = strip(&webp, MetadataPolicy::Safe).unwrap();
+        assert_eq!(
+            &safe[8..],
+            b"WEBPVP8X\x0a\0\0\0\x20\0\0\0\0\0\0\0\0\0ICCP\x03\0\0\0icc\0VP8L\x02\0\0\0\x2f\0"
+        );
+        assert_eq!(safe[4..8], ((safe.len() - 8) as u32).to_le_bytes());
+
+        let stripped = strip(&webp, MetadataPolicy::Strip).unwrap();
+        assert_eq!(
+            &stripped[8..],
+            b"WEBPVP8X\x0a\0\0\0\0\0\0\0\0\0\0\0\0\0VP8L\x02\0\0\0\x2f\0"
+        );
+
+        // Not a format that can be rewritten
+        assert_eq!(strip(b"GIF89a", MetadataPolicy::Strip), None);
+    }
+}
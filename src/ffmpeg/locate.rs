@@ -0,0 +1,167 @@
+#[cfg(embedded_ffmpeg)]
+use super::embedded;
+use anyhow::{Result, bail};
+use std::env;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Environment variable pointing at the ffmpeg binary to use.
+pub const FFMPEG_ENV: &str = "CRUNCH_FFMPEG";
+
+#[cfg(target_os = "windows")]
+const FFMPEG_NAME: &str = "ffmpeg.exe";
+
+#[cfg(not(target_os = "windows"))]
+const FFMPEG_NAME: &str = "ffmpeg";
+
+/// Where the ffmpeg binary crunch runs came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FfmpegSource {
+    Flag,
+    Env,
+    Path,
+    #[cfg(embedded_ffmpeg)]
+    Embedded,
+}
+
+impl FfmpegSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Flag => "--ffmpeg",
+            Self::Env => FFMPEG_ENV,
+            Self::Path => "PATH",
+            #[cfg(embedded_ffmpeg)]
+            Self::Embedded => "embedded",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Ffmpeg {
+    pub path: PathBuf,
+    pub source: FfmpegSource,
+}
+
+/// Find the ffmpeg binary to use: `--ffmpeg`, then `CRUNCH_FFMPEG`, then `ffmpeg` on
+/// PATH, and only then the embedded build (when crunch was built with one).
+///
+/// An explicit `--ffmpeg` or `CRUNCH_FFMPEG` that doesn't resolve is an error rather than
+/// a silent fallback, so a typo doesn't end up encoding with a different ffmpeg.
+pub fn get_ffmpeg(flag: Option<&Path>) -> Result<Ffmpeg> {
+    let path_var = env::var_os("PATH").unwrap_or_default();
+
+    if let Some(flag) = flag {
+        return match resolve(flag, &path_var) {
+            Some(path) => Ok(Ffmpeg {
+                path,
+                source: FfmpegSource::Flag,
+            }),
+            None => bail!("ffmpeg not found: {}", flag.display()),
+        };
+    }
+
+    if let Some(value) = env::var_os(FFMPEG_ENV).filter(|value| !value.is_empty()) {
+        return match resolve(Path::new(&value), &path_var) {
+            Some(path) => Ok(Ffmpeg {
+                path,
+                source: FfmpegSource::Env,
+            }),
+            None => bail!(
+                "ffmpeg not found: {} (from {})",
+                Path::new(&value).display(),
+                FFMPEG_ENV
+            ),
+        };
+    }
+
+    if let Some(path) = find_in_path(OsStr::new(FFMPEG_NAME), &path_var) {
+        return Ok(Ffmpeg {
+            path,
+            source: FfmpegSource::Path,
+        });
+    }
+
+    #[cfg(embedded_ffmpeg)]
+    return Ok(Ffmpeg {
+        path: embedded::extract(FFMPEG_NAME)?,
+        source: FfmpegSource::Embedded,
+    });
+
+    #[cfg(not(embedded_ffmpeg))]
+    bail!(
+        "ffmpeg not found. Install ffmpeg and add it to PATH, or point --ffmpeg or {} at it \
+         (this build of crunch doesn't embed one)",
+        FFMPEG_ENV
+    )
+}
+
+/// A user-given ffmpeg: a path to the binary, or a bare name looked up on PATH.
+fn resolve(ffmpeg: &Path, path_var: &OsStr) -> Option<PathBuf> {
+    if is_executable(ffmpeg) {
+        return Some(ffmpeg.to_path_buf());
+    }
+
+    // "ffmpeg-6" rather than "./ffmpeg-6"
+    if ffmpeg.components().count() == 1 {
+        return find_in_path(ffmpeg.as_os_str(), path_var);
+    }
+
+    None
+}
+
+/// Look `name` up in the directories of a PATH-style variable.
+fn find_in_path(name: &OsStr, path_var: &OsStr) -> Option<PathBuf> {
+    env::split_paths(path_var)
+        .map(|dir| dir.join(name))
+        .find(|candidate| is_executable(candidate))
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path).is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::TempDir;
+
+    fn fake_binary(dir: &Path, name: &str, mode: u32) -> PathBuf {
+        let path = dir.join(name);
+        fs::write(&path, b"#!/bin/sh\n").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_find_in_path() {
+        let first = TempDir::new().unwrap();
+        let second = TempDir::new().unwrap();
+        // Not executable, so the lookup moves on to the next directory
+        fake_binary(first.path(), "ffmpeg", 0o644);
+        let ffmpeg = fake_binary(second.path(), "ffmpeg", 0o755);
+
+        let path_var = env::join_paths([first.path(), second.path()]).unwrap();
+        assert_eq!(
+            find_in_path(OsStr::new("ffmpeg"), &path_var),
+            Some(ffmpeg.clone())
+        );
+        assert_eq!(find_in_path(OsStr::new("ffprobe"), &path_var), None);
+
+        // Bare names go through PATH, paths are taken as they are
+        assert_eq!(
+            resolve(Path::new("ffmpeg"), &path_var),
+            Some(ffmpeg.clone())
+        );
+        assert_eq!(resolve(&ffmpeg, OsStr::new("")), Some(ffmpeg));
+        assert_eq!(resolve(&first.path().join("ffmpeg"), &path_var), None);
+    }
+}
@@ -0,0 +1,135 @@
+use super::probe::MediaInfo;
+
+/// What `--metadata` keeps of an input's metadata.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MetadataPolicy {
+    /// Drop all tags, chapters and ICC profiles
+    Strip,
+    /// Copy everything ffmpeg can carry over
+    Keep,
+    /// Drop location and device identifiers, keep descriptive tags, chapters and ICC profiles
+    /// (except in encoded WebP, which ffmpeg writes without one)
+    #[default]
+    Safe,
+}
+
+/// Global tags `safe` carries over. Anything else, e.g. "location",
+/// "com.apple.quicktime.make" or "encoder", is dropped.
+const SAFE_TAGS: &[&str] = &[
+    "title",
+    "artist",
+    "album",
+    "album_artist",
+    "composer",
+    "performer",
+    "genre",
+    "track",
+    "disc",
+    "date",
+    "copyright",
+    "license",
+    "language",
+];
+
+impl MetadataPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Strip => "strip",
+            Self::Keep => "keep",
+            Self::Safe => "safe",
+        }
+    }
+
+    /// Output options for an input with `info`.
+    ///
+    /// Orientation needs nothing here: ffmpeg rotates video upright while decoding.
+    pub fn args(&self, info: &MediaInfo) -> Vec<String> {
+        let (metadata, chapters) = match self {
+            Self::Strip => ("-1", "-1"),
+            Self::Keep => ("0", "0"),
+            Self::Safe => ("-1", "0"),
+        };
+        let mut args = vec![
+            "-map_metadata".to_string(),
+            metadata.to_string(),
+            "-map_chapters".to_string(),
+            chapters.to_string(),
+        ];
+
+        // Nothing is copied automatically, so the allowed tags are set one by one
+        if *self == Self::Safe {
+            for (key, value) in &info.tags {
+                if SAFE_TAGS.contains(&key.to_lowercase().as_str()) {
+                    args.push("-metadata".to_string());
+                    args.push(format!("{}={}", key, value));
+                }
+            }
+        }
+
+        args
+    }
+
+    /// The `-movflags` mp4 and mov need to write tags they have no standard atom for.
+    pub fn movflags(&self) -> Option<&'static str> {
+        match self {
+            Self::Keep => Some("+use_metadata_tags"),
+            _ => None,
+        }
+    }
+
+    /// The `-vf` filter that drops ICC profiles, which the JPEG, PNG and AVIF encoders otherwise
+    /// pass through. ffmpeg's WebP muxer has no ICC chunk, so WebP outputs never keep one.
+    pub fn filter(&self) -> Option<&'static str> {
+        match self {
+            Self::Strip => Some("sidedata=mode=delete:type=ICC_PROFILE"),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_args() {
+        let info = MediaInfo {
+            tags: BTreeMap::from([
+                ("copyright".to_string(), "(c) 2024 Jane".to_string()),
+                ("title".to_string(), "Harbour".to_string()),
+                ("location".to_string(), "+52.3740+004.8897/".to_string()),
+                (
+                    "com.apple.quicktime.model".to_string(),
+                    "iPhone 15".to_string(),
+                ),
+            ]),
+            ..MediaInfo::default()
+        };
+
+        assert_eq!(
+            MetadataPolicy::Safe.args(&info),
+            vec![
+                "-map_metadata",
+                "-1",
+                "-map_chapters",
+                "0",
+                "-metadata",
+                "copyright=(c) 2024 Jane",
+                "-metadata",
+                "title=Harbour",
+            ]
+        );
+        assert_eq!(
+            MetadataPolicy::Strip.args(&info),
+            vec!["-map_metadata", "-1", "-map_chapters", "-1"]
+        );
+        assert_eq!(
+            MetadataPolicy::Keep.args(&info),
+            vec!["-map_metadata", "0", "-map_chapters", "0"]
+        );
+
+        assert!(MetadataPolicy::Strip.filter().is_some());
+        assert_eq!(MetadataPolicy::Safe.filter(), None);
+    }
+}
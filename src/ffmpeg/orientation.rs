@@ -0,0 +1,195 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// The EXIF tag holding the orientation.
+const ORIENTATION_TAG: u16 = 0x0112;
+
+/// How far into a JPEG the EXIF segment is looked for. It comes right after the
+/// start of the file, and a segment can't be larger than 64 KiB.
+const MAX_HEADER: u64 = 256 * 1024;
+
+/// The EXIF orientation of a photo, 1-8.
+///
+/// Cameras store the pixels as the sensor saw them and record how to turn them upright
+/// in this tag. Encoders drop the tag, so crunch turns the pixels themselves instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Orientation(u8);
+
+impl Orientation {
+    /// The orientation of the JPEG at `path`, or `None` when it has none.
+    pub fn read(path: &Path) -> Option<Self> {
+        let mut header = Vec::new();
+        File::open(path)
+            .ok()?
+            .take(MAX_HEADER)
+            .read_to_end(&mut header)
+            .ok()?;
+
+        Self::parse(&header)
+    }
+
+    /// The orientation of a JPEG already read into memory.
+    pub fn parse(jpeg: &[u8]) -> Option<Self> {
+        parse_jpeg(jpeg)
+    }
+
+    pub fn is_upright(&self) -> bool {
+        self.0 == 1
+    }
+
+    /// The payload of an APP1 segment holding this orientation and nothing else.
+    pub fn exif(&self) -> Vec<u8> {
+        let mut exif = b"Exif\0\0MM\0\x2a".to_vec();
+        // The first IFD right after the header, with a single entry
+        exif.extend(8u32.to_be_bytes());
+        exif.extend(1u16.to_be_bytes());
+        exif.extend(ORIENTATION_TAG.to_be_bytes());
+        exif.extend(3u16.to_be_bytes()); // SHORT
+        exif.extend(1u32.to_be_bytes());
+        exif.extend([0, self.0, 0, 0]);
+        // No next IFD
+        exif.extend(0u32.to_be_bytes());
+        exif
+    }
+
+    /// The `-vf` filter that turns the stored pixels upright, `None` when they already are.
+    pub fn filter(&self) -> Option<&'static str> {
+        let filter = match self.0 {
+            2 => "hflip",
+            3 => "hflip,vflip",
+            4 => "vflip",
+            // Mirrored along the top-left to bottom-right diagonal
+            5 => "transpose=cclock_flip",
+            6 => "transpose=clock",
+            // Mirrored along the top-right to bottom-left diagonal
+            7 => "transpose=clock_flip",
+            8 => "transpose=cclock",
+            _ => return None,
+        };
+        Some(filter)
+    }
+
+    /// Whether the upright image is the stored one turned on its side.
+    pub fn swaps_dimensions(&self) -> bool {
+        self.0 >= 5
+    }
+}
+
+/// Walk the JPEG segments up to the image data, looking for an APP1 "Exif" segment.
+fn parse_jpeg(data: &[u8]) -> Option<Orientation> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+
+    let mut pos = 2;
+    while pos + 4 <= data.len() {
+        if data[pos] != 0xFF {
+            return None;
+        }
+        let marker = data[pos + 1];
+        // Start of scan, the metadata segments are all behind us
+        if marker == 0xDA {
+            return None;
+        }
+        let length = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        let segment = data.get(pos + 4..pos + 2 + length)?;
+
+        if marker == 0xE1
+            && let Some(tiff) = segment.strip_prefix(b"Exif\0\0")
+        {
+            return parse_tiff(tiff);
+        }
+        pos += 2 + length;
+    }
+
+    None
+}
+
+/// Find the orientation in the first IFD of the TIFF structure EXIF data is stored as.
+fn parse_tiff(tiff: &[u8]) -> Option<Orientation> {
+    let big_endian = match tiff.get(..2)? {
+        b"II" => false,
+        b"MM" => true,
+        _ => return None,
+    };
+    let u16_at = |pos: usize| {
+        let bytes = [*tiff.get(pos)?, *tiff.get(pos + 1)?];
+        Some(if big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    };
+    let u32_at = |pos: usize| {
+        let bytes: [u8; 4] = tiff.get(pos..pos + 4)?.try_into().ok()?;
+        Some(if big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    };
+
+    if u16_at(2)? != 42 {
+        return None;
+    }
+    let ifd = u32_at(4)? as usize;
+    let entries = u16_at(ifd)? as usize;
+
+    // Entries are 12 bytes: tag, type, count, then the value itself when it fits
+    for entry in 0..entries {
+        let pos = ifd + 2 + entry * 12;
+        if u16_at(pos)? == ORIENTATION_TAG {
+            let value = u16_at(pos + 8)?;
+            return (1..=8).contains(&value).then_some(Orientation(value as u8));
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ffmpeg::test_util::exif_jpeg;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_read_every_orientation() {
+        let temp_dir = TempDir::new().unwrap();
+        let expected = [
+            (1, None, false),
+            (2, Some("hflip"), false),
+            (3, Some("hflip,vflip"), false),
+            (4, Some("vflip"), false),
+            (5, Some("transpose=cclock_flip"), true),
+            (6, Some("transpose=clock"), true),
+            (7, Some("transpose=clock_flip"), true),
+            (8, Some("transpose=cclock"), true),
+        ];
+
+        for (value, filter, swaps) in expected {
+            for big_endian in [false, true] {
+                let path = temp_dir
+                    .path()
+                    .join(format!("{}-{}.jpg", value, big_endian));
+                std::fs::write(&path, exif_jpeg(value, big_endian)).unwrap();
+
+                let orientation = Orientation::read(&path).unwrap();
+                assert_eq!(orientation, Orientation(value as u8));
+                assert_eq!(orientation.filter(), filter);
+                assert_eq!(orientation.swaps_dimensions(), swaps);
+            }
+        }
+    }
+
+    #[test]
+    fn test_no_orientation() {
+        assert_eq!(parse_jpeg(&exif_jpeg(0, false)), None);
+        assert_eq!(parse_jpeg(&exif_jpeg(9, true)), None);
+        // Not a JPEG
+        assert_eq!(parse_jpeg(b"\x89PNG\r\n\x1a\n"), None);
+        // Truncated in the middle of the EXIF segment
+        assert_eq!(parse_jpeg(&exif_jpeg(6, false)[..30]), None);
+    }
+}
@@ -0,0 +1,104 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// A temporary file next to `output` that ffmpeg writes to.
+///
+/// It only replaces `output` once [`PartialOutput::persist`] is called after ffmpeg
+/// succeeded, so a failed or interrupted encode never leaves a truncated file under the
+/// real name. Dropping it without persisting removes the temporary file.
+#[derive(Debug)]
+pub struct PartialOutput {
+    tmp: PathBuf,
+    output: PathBuf,
+    persisted: bool,
+}
+
+impl PartialOutput {
+    /// e.g. "out/photo.webp" → "out/.photo.crunch-1234.webp". The extension is kept so
+    /// ffmpeg still picks the right muxer, the pid keeps concurrent crunch runs apart.
+    pub fn new(output: &Path) -> Self {
+        let stem = output
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or("output");
+        let extension = output
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or_default();
+
+        let tmp_name = format!(".{}.crunch-{}.{}", stem, process::id(), extension);
+
+        Self {
+            tmp: output.with_file_name(tmp_name),
+            output: output.to_path_buf(),
+            persisted: false,
+        }
+    }
+
+    /// The temporary path to hand to ffmpeg.
+    pub fn path(&self) -> &Path {
+        &self.tmp
+    }
+
+    /// Rename the temporary file onto the real output.
+    pub fn persist(mut self) -> Result<PathBuf> {
+        fs::rename(&self.tmp, &self.output).with_context(|| {
+            format!(
+                "Failed to move output into place: {}",
+                self.output.display()
+            )
+        })?;
+        self.persisted = true;
+
+        Ok(self.output.clone())
+    }
+}
+
+impl Drop for PartialOutput {
+    fn drop(&mut self) {
+        if !self.persisted {
+            let _ = fs::remove_file(&self.tmp);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_partial_output_persist() {
+        let temp_dir = TempDir::new().unwrap();
+        let output = temp_dir.path().join("photo.webp");
+
+        let partial = PartialOutput::new(&output);
+        assert_eq!(partial.path().parent(), output.parent());
+        assert_eq!(partial.path().extension().unwrap(), "webp");
+
+        fs::write(partial.path(), b"encoded").unwrap();
+        let tmp = partial.path().to_path_buf();
+        assert_eq!(partial.persist().unwrap(), output);
+
+        assert!(!tmp.exists());
+        assert_eq!(fs::read(&output).unwrap(), b"encoded");
+    }
+
+    #[test]
+    fn test_partial_output_dropped() {
+        let temp_dir = TempDir::new().unwrap();
+        let output = temp_dir.path().join("clip.mp4");
+        fs::write(&output, b"previous run").unwrap();
+
+        let partial = PartialOutput::new(&output);
+        fs::write(partial.path(), b"trunc").unwrap();
+        let tmp = partial.path().to_path_buf();
+        drop(partial);
+
+        // The failed encode is cleaned up and the existing output is untouched
+        assert!(!tmp.exists());
+        assert_eq!(fs::read(&output).unwrap(), b"previous run");
+    }
+}
@@ -0,0 +1,435 @@
+use super::batch::{Batch, Target, run_batch};
+use super::compress::{BaseCompressOptions, Outcome};
+use super::partial::PartialOutput;
+use super::probe::{MediaInfo, parse_timestamp};
+use super::runner::run_ffmpeg;
+use crate::summary::{FileRecord, MediaKind};
+use anyhow::{Context, Result, bail};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Width of each contact sheet thumbnail and of the animated preview.
+const THUMBNAIL_WIDTH: u32 = 320;
+
+/// Length of the animated preview.
+const PREVIEW_LENGTH: Duration = Duration::from_secs(3);
+
+/// Frame rate of the animated preview.
+const PREVIEW_FPS: u32 = 12;
+
+/// Where in the video the poster frame is taken.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PosterAt {
+    /// The first scene change, or the most representative frame when there is none
+    Scene,
+    Time(Duration),
+}
+
+impl PosterAt {
+    /// "scene", seconds ("12.5") or a timestamp ("1:30", "00:01:30.5").
+    pub fn parse(value: &str) -> Result<Self> {
+        if value == "scene" {
+            return Ok(Self::Scene);
+        }
+
+        let time = if value.contains(':') {
+            let padded = if value.matches(':').count() == 1 {
+                format!("0:{}", value)
+            } else {
+                value.to_string()
+            };
+            parse_timestamp(&padded)
+        } else {
+            value
+                .parse::<f64>()
+                .ok()
+                .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+        };
+
+        match time {
+            Some(time) => Ok(Self::Time(time)),
+            None => bail!(
+                "Invalid poster time '{}', use seconds, MM:SS, HH:MM:SS or 'scene'",
+                value
+            ),
+        }
+    }
+
+    fn as_string(&self) -> String {
+        match self {
+            Self::Scene => "scene".to_string(),
+            Self::Time(time) => format!("{:.3}s", time.as_secs_f64()),
+        }
+    }
+}
+
+/// Format of the animated `--preview`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PreviewFormat {
+    /// With a palette generated from the clip itself
+    Gif,
+    WebP,
+}
+
+impl PreviewFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            Self::Gif => "gif",
+            Self::WebP => "webp",
+        }
+    }
+
+    fn encoder(&self) -> &'static str {
+        match self {
+            Self::Gif => "gif",
+            Self::WebP => "libwebp",
+        }
+    }
+}
+
+/// What to extract from each video, written next to its compressed output as
+/// `clip.poster.jpg`, `clip.sheet.jpg` and `clip.preview.gif`.
+pub struct PreviewOptions {
+    pub poster: Option<PosterAt>,
+    /// Number of thumbnails on the contact sheet
+    pub contact_sheet: Option<u32>,
+    pub preview: Option<PreviewFormat>,
+    pub base: BaseCompressOptions,
+}
+
+/// One of the images extracted per video.
+#[derive(Debug, Clone, Copy)]
+enum Extract {
+    Poster(PosterAt),
+    ContactSheet(u32),
+    Preview(PreviewFormat),
+}
+
+impl Extract {
+    fn suffix(&self) -> String {
+        match self {
+            Self::Poster(_) => "poster.jpg".to_string(),
+            Self::ContactSheet(_) => "sheet.jpg".to_string(),
+            Self::Preview(format) => format!("preview.{}", format.extension()),
+        }
+    }
+
+    /// What the extract is recorded as, in place of an encoder. The poster and the
+    /// contact sheet share one, so the manifest and the summary tell them apart by this.
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Poster(_) => "poster",
+            Self::ContactSheet(_) => "contact_sheet",
+            Self::Preview(_) => "preview",
+        }
+    }
+
+    fn encoder(&self) -> &'static str {
+        match self {
+            Self::Poster(_) | Self::ContactSheet(_) => "mjpeg",
+            Self::Preview(format) => format.encoder(),
+        }
+    }
+
+    fn settings(&self) -> BTreeMap<String, String> {
+        let mut settings = match self {
+            Self::Poster(at) => vec![("at", at.as_string())],
+            Self::ContactSheet(count) => vec![
+                ("thumbnails", count.to_string()),
+                ("thumbnail_width", THUMBNAIL_WIDTH.to_string()),
+            ],
+            Self::Preview(_) => vec![
+                ("length", PREVIEW_LENGTH.as_secs().to_string()),
+                ("fps", PREVIEW_FPS.to_string()),
+            ],
+        };
+        settings.push(("encoder", self.encoder().to_string()));
+
+        settings
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value))
+            .collect()
+    }
+}
+
+impl PreviewOptions {
+    fn extracts(&self) -> Vec<Extract> {
+        let mut extracts = Vec::new();
+        if let Some(at) = self.poster {
+            extracts.push(Extract::Poster(at));
+        }
+        if let Some(count) = self.contact_sheet {
+            extracts.push(Extract::ContactSheet(count));
+        }
+        if let Some(format) = self.preview {
+            extracts.push(Extract::Preview(format));
+        }
+        extracts
+    }
+}
+
+/// Extract the poster, contact sheet and preview of every video, one record each.
+pub fn extract_all_previews(
+    ffmpeg: &Path,
+    videos: &[PathBuf],
+    options: &PreviewOptions,
+) -> Result<Vec<FileRecord>> {
+    if !ffmpeg.exists() {
+        bail!("FFmpeg executable not found at: {}", ffmpeg.display());
+    }
+
+    let extracts = options.extracts();
+    let batch = Batch {
+        ffmpeg,
+        kind: MediaKind::Preview,
+        targets: extracts
+            .iter()
+            .map(|extract| Target {
+                base: &options.base,
+                codec: extract.name(),
+                settings: extract.settings(),
+            })
+            .collect(),
+        file_bars: false,
+        finish_message: "Preview extraction complete",
+    };
+
+    Ok(run_batch(videos, batch, |video, info, pending, _| {
+        pending
+            .iter()
+            .map(|&index| extract_preview(ffmpeg, video, info, &options.base, extracts[index]))
+            .collect()
+    }))
+}
+
+fn extract_preview(
+    ffmpeg: &Path,
+    input: &Path,
+    info: &MediaInfo,
+    base: &BaseCompressOptions,
+    extract: Extract,
+) -> Result<Outcome> {
+    let output = base.output_file_with_extension(input, &extract.suffix())?;
+    if base.is_up_to_date(input, extract.name(), &output)? {
+        return Ok(Outcome::Skipped("up to date".to_string()));
+    }
+
+    let attempts = match extract {
+        Extract::Poster(at) => poster_args(input, info, base, at)?,
+        Extract::ContactSheet(count) => vec![contact_sheet_args(input, info, count)?],
+        Extract::Preview(format) => vec![preview_args(input, info, format)?],
+    };
+
+    let partial = PartialOutput::new(&output);
+    base.mark_started(input, extract.name(), partial.path())?;
+    let partial_path = partial
+        .path()
+        .to_str()
+        .context("Invalid output path")?
+        .to_string();
+
+    // A filter that selects no frame leaves ffmpeg nothing to write, and some ffmpeg
+    // versions fail on it instead, so either way the next attempt gets its turn
+    let mut error = None;
+    for mut args in attempts {
+        args.extend(["-y".to_string(), partial_path.clone()]);
+        let result = run_ffmpeg(ffmpeg, &args)?;
+        if result.status.success() && fs::metadata(partial.path()).is_ok_and(|meta| meta.len() > 0)
+        {
+            return Ok(Outcome::Compressed(partial.persist()?));
+        }
+        error = (!result.status.success())
+            .then(|| String::from_utf8_lossy(&result.stderr).into_owned());
+    }
+
+    match error {
+        Some(stderr) => bail!(
+            "Failed to extract the {} of {}: {}",
+            extract.suffix(),
+            input.display(),
+            stderr
+        ),
+        None => bail!(
+            "ffmpeg found no frame for the {} of {}",
+            extract.suffix(),
+            input.display()
+        ),
+    }
+}
+
+/// The ffmpeg runs to try for the poster frame, in order, each without its output.
+fn poster_args(
+    input: &Path,
+    info: &MediaInfo,
+    base: &BaseCompressOptions,
+    at: PosterAt,
+) -> Result<Vec<Vec<String>>> {
+    let input = input.to_str().context("Invalid input path")?.to_string();
+    let scale = base.resize.filter(info, false, "lanczos");
+    let chain = |filter: &str| match &scale {
+        Some(scale) => format!("{},{}", filter, scale),
+        None => filter.to_string(),
+    };
+    let encode = ["-frames:v", "1", "-q:v", "2"].map(str::to_string);
+
+    let attempts = match at {
+        PosterAt::Time(mut time) => {
+            // Past the end there is no frame to take, the middle is the next best thing
+            if let Some(duration) = info.duration
+                && time >= duration
+            {
+                time = duration / 2;
+            }
+            let mut args = vec![
+                "-ss".to_string(),
+                format!("{:.3}", time.as_secs_f64()),
+                "-i".to_string(),
+                input,
+            ];
+            if let Some(scale) = &scale {
+                args.extend(["-vf".to_string(), scale.clone()]);
+            }
+            args.extend(encode);
+            vec![args]
+        }
+        // A video without a clear scene change falls back to ffmpeg's pick of a
+        // representative frame
+        PosterAt::Scene => ["select='gt(scene,0.3)'", "thumbnail=n=150"]
+            .iter()
+            .map(|filter| {
+                let mut args = vec![
+                    "-i".to_string(),
+                    input.clone(),
+                    "-vf".to_string(),
+                    chain(filter),
+                ];
+                args.extend(encode.clone());
+                args
+            })
+            .collect(),
+    };
+
+    Ok(attempts)
+}
+
+/// `count` thumbnails spread evenly over the video, tiled into a near-square grid.
+fn contact_sheet_args(input: &Path, info: &MediaInfo, count: u32) -> Result<Vec<String>> {
+    let columns = (count as f64).sqrt().ceil() as u32;
+    let rows = count.div_ceil(columns);
+
+    let mut args = Vec::new();
+    let sample = match info.duration {
+        Some(duration) if !duration.is_zero() => {
+            // Each thumbnail comes from the middle of its share of the video
+            let interval = duration.as_secs_f64() / count as f64;
+            args.extend(["-ss".to_string(), format!("{:.3}", interval / 2.0)]);
+            format!("fps=1/{:.6}", interval)
+        }
+        _ => "thumbnail=n=50".to_string(),
+    };
+    args.extend([
+        "-i".to_string(),
+        input.to_str().context("Invalid input path")?.to_string(),
+        "-vf".to_string(),
+        format!(
+            "{},scale={}:-2:flags=lanczos,tile={}x{}",
+            sample, THUMBNAIL_WIDTH, columns, rows
+        ),
+        "-frames:v".to_string(),
+        "1".to_string(),
+        "-q:v".to_string(),
+        "3".to_string(),
+    ]);
+
+    Ok(args)
+}
+
+/// A short muted clip from a tenth of the way in.
+fn preview_args(input: &Path, info: &MediaInfo, format: PreviewFormat) -> Result<Vec<String>> {
+    let start = info
+        .duration
+        .map_or(Duration::ZERO, |duration| duration / 10);
+    let scale = format!(
+        "fps={},scale='min({},iw)':-2:flags=lanczos",
+        PREVIEW_FPS, THUMBNAIL_WIDTH
+    );
+
+    let mut args = vec![
+        "-ss".to_string(),
+        format!("{:.3}", start.as_secs_f64()),
+        "-t".to_string(),
+        PREVIEW_LENGTH.as_secs().to_string(),
+        "-i".to_string(),
+        input.to_str().context("Invalid input path")?.to_string(),
+        "-an".to_string(),
+    ];
+    match format {
+        // A palette made from the clip looks far better than the default 256 colors
+        PreviewFormat::Gif => args.extend([
+            "-vf".to_string(),
+            format!(
+                "{},split[a][b];[a]palettegen=stats_mode=diff[p];[b][p]paletteuse=dither=bayer:bayer_scale=5",
+                scale
+            ),
+        ]),
+        PreviewFormat::WebP => args.extend([
+            "-vf".to_string(),
+            scale,
+            "-c:v".to_string(),
+            "libwebp".to_string(),
+            "-quality".to_string(),
+            "60".to_string(),
+            "-compression_level".to_string(),
+            "4".to_string(),
+        ]),
+    }
+    // Loop forever
+    args.extend(["-loop".to_string(), "0".to_string()]);
+
+    Ok(args)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_poster_at() {
+        assert_eq!(PosterAt::parse("scene").unwrap(), PosterAt::Scene);
+        assert_eq!(
+            PosterAt::parse("12.5").unwrap(),
+            PosterAt::Time(Duration::from_millis(12_500))
+        );
+        assert_eq!(
+            PosterAt::parse("1:30").unwrap(),
+            PosterAt::Time(Duration::from_secs(90))
+        );
+        assert_eq!(
+            PosterAt::parse("01:00:02").unwrap(),
+            PosterAt::Time(Duration::from_secs(3602))
+        );
+        assert!(PosterAt::parse("-3").is_err());
+        assert!(PosterAt::parse("soon").is_err());
+    }
+
+    #[test]
+    fn test_contact_sheet_args() {
+        let info = MediaInfo {
+            duration: Some(Duration::from_secs(90)),
+            ..MediaInfo::default()
+        };
+        let args = contact_sheet_args(Path::new("clip.mp4"), &info, 10).unwrap();
+
+        // 10 thumbnails, one every 9s starting at 4.5s, on a 4x3 grid
+        assert_eq!(args[..2], ["-ss", "4.500"]);
+        assert!(args.contains(&"fps=1/9.000000,scale=320:-2:flags=lanczos,tile=4x3".to_string()));
+
+        let unknown = contact_sheet_args(Path::new("clip.mp4"), &MediaInfo::default(), 4).unwrap();
+        assert_eq!(unknown[0], "-i");
+        assert!(
+            unknown.contains(&"thumbnail=n=50,scale=320:-2:flags=lanczos,tile=2x2".to_string())
+        );
+    }
+}
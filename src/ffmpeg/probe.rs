@@ -0,0 +1,311 @@
+use super::animation::Animation;
+use super::orientation::Orientation;
+use super::runner::run_ffmpeg;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::Duration;
+
+/// What ffmpeg reports about an input file. Anything it doesn't report is `None`.
+///
+/// The size is the upright size, after the rotation of a phone video or the EXIF
+/// orientation of a photo, as that's the size filters see.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MediaInfo {
+    pub duration: Option<Duration>,
+    /// Overall bitrate in bits per second
+    pub bitrate: Option<u64>,
+    /// Of the first video stream, e.g. "h264". Cover art isn't counted as video.
+    pub video_codec: Option<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub fps: Option<f64>,
+    /// Of the first video stream, in bits per second
+    pub video_bitrate: Option<u64>,
+    /// Of the first audio stream, e.g. "aac"
+    pub audio_codec: Option<String>,
+    pub channels: Option<u8>,
+    pub sample_rate: Option<u32>,
+    /// Of the first audio stream, in bits per second
+    pub audio_bitrate: Option<u64>,
+    /// Tags of the input as a whole, e.g. "title" or "location"
+    pub tags: BTreeMap<String, String>,
+    /// From the EXIF data of a JPEG, which ffmpeg doesn't apply
+    pub orientation: Option<Orientation>,
+    /// For animated GIF, APNG and WebP images, `None` for stills
+    pub animation: Option<Animation>,
+}
+
+/// Read the input info ffmpeg prints for `input`.
+///
+/// Inputs ffmpeg can't read come back empty, the encode itself reports the error.
+pub fn probe_media(ffmpeg: &Path, input: &Path) -> MediaInfo {
+    let Some(input) = input.to_str() else {
+        return MediaInfo::default();
+    };
+
+    // Without an output file ffmpeg exits with an error, but it still prints the input info
+    let args = [
+        "-hide_banner".to_string(),
+        "-i".to_string(),
+        input.to_string(),
+    ];
+    let mut info = match run_ffmpeg(ffmpeg, &args) {
+        Ok(result) => parse_media_info(&String::from_utf8_lossy(&result.stderr)),
+        Err(_) => MediaInfo::default(),
+    };
+
+    info.orientation = Orientation::read(Path::new(input));
+    info.animation = Animation::read(Path::new(input));
+    if info.orientation.is_some_and(|o| o.swaps_dimensions()) {
+        (info.width, info.height) = (info.height, info.width);
+    }
+
+    info
+}
+
+/// Parse the "Duration: ..." and "Stream #0:0: Video: ..." lines of ffmpeg's input info,
+/// the "Metadata:" block of the input above them and the rotation of the video stream.
+fn parse_media_info(stderr: &str) -> MediaInfo {
+    let mut info = MediaInfo::default();
+    // Streams have "Metadata:" blocks of their own, below the "Duration: ..." line
+    let mut in_header = true;
+    let mut in_tags = false;
+    // Below the line of the video stream, until the next stream
+    let mut in_video = false;
+    let mut rotation = None;
+
+    for line in stderr.lines().map(str::trim) {
+        if let Some(rest) = line.strip_prefix("Duration: ") {
+            in_header = false;
+            for part in rest.split(", ") {
+                if let Some(bitrate) = part.strip_prefix("bitrate: ") {
+                    info.bitrate = parse_bitrate(bitrate);
+                } else if info.duration.is_none() {
+                    info.duration = parse_timestamp(part);
+                }
+            }
+        } else if let Some(stream) = line.strip_prefix("Stream #") {
+            in_header = false;
+            in_video = false;
+            if let Some((_, video)) = stream.split_once(": Video: ") {
+                if info.video_codec.is_none() && !video.contains("(attached pic)") {
+                    parse_video_stream(video, &mut info);
+                    in_video = true;
+                }
+            } else if let Some((_, audio)) = stream.split_once(": Audio: ")
+                && info.audio_codec.is_none()
+            {
+                parse_audio_stream(audio, &mut info);
+            }
+        } else if in_video && let Some(degrees) = parse_rotation(line) {
+            rotation = Some(degrees);
+        } else if line == "Metadata:" {
+            in_tags = in_header;
+        } else if in_tags
+            && in_header
+            && let Some((key, value)) = line.split_once(':')
+        {
+            // Continuation lines of a multi-line value have no key
+            let key = key.trim();
+            if !key.is_empty() {
+                info.tags.insert(key.to_string(), value.trim().to_string());
+            }
+        }
+    }
+
+    // ffmpeg turns the frames upright before any filter runs
+    if rotation.is_some_and(|degrees: f64| degrees.abs().round() as u32 % 180 == 90) {
+        (info.width, info.height) = (info.height, info.width);
+    }
+
+    info
+}
+
+/// The rotation of a video stream in degrees, from its "displaymatrix: rotation of
+/// -90.00 degrees" side data, or its "rotate : 90" tag on older ffmpeg versions.
+fn parse_rotation(line: &str) -> Option<f64> {
+    if let Some(rest) = line.strip_prefix("displaymatrix: rotation of ") {
+        return rest.strip_suffix(" degrees")?.parse().ok();
+    }
+    let (key, value) = line.split_once(':')?;
+    if key.trim() != "rotate" {
+        return None;
+    }
+    value.trim().parse().ok()
+}
+
+/// e.g. "h264 (High) (avc1 / 0x31637661), yuv420p(progressive), 1920x1080 [SAR 1:1 DAR 16:9], 1069 kb/s, 30 fps, 30 tbr"
+fn parse_video_stream(stream: &str, info: &mut MediaInfo) {
+    info.video_codec = codec_name(stream);
+
+    for part in stream.split(", ") {
+        let part = part.trim();
+        if let Some(fps) = part.strip_suffix(" fps") {
+            info.fps = fps.parse().ok();
+        } else if part.ends_with(" kb/s") {
+            info.video_bitrate = parse_bitrate(part);
+        } else if let Some(token) = part.split_whitespace().next()
+            && let Some((width, height)) = token.split_once('x')
+            && let (Ok(width), Ok(height)) = (width.parse(), height.parse())
+        {
+            info.width = Some(width);
+            info.height = Some(height);
+        }
+    }
+}
+
+/// e.g. "aac (LC) (mp4a / 0x6134706D), 48000 Hz, stereo, fltp, 128 kb/s (default)"
+fn parse_audio_stream(stream: &str, info: &mut MediaInfo) {
+    info.audio_codec = codec_name(stream);
+
+    // The codec part comes first, so layouts like "5.1" can't be mistaken for anything else
+    for part in stream.split(", ").skip(1) {
+        let part = part.trim();
+        if let Some(rate) = part.strip_suffix(" Hz") {
+            info.sample_rate = rate.parse().ok();
+        } else if part.contains(" kb/s") {
+            info.audio_bitrate = parse_bitrate(part);
+        } else if info.channels.is_none() {
+            info.channels = parse_channels(part);
+        }
+    }
+}
+
+fn codec_name(stream: &str) -> Option<String> {
+    let name = stream.split_whitespace().next()?.trim_end_matches(',');
+    Some(name.to_string())
+}
+
+/// "1205 kb/s" → 1205000 bits per second. "N/A" → None.
+fn parse_bitrate(value: &str) -> Option<u64> {
+    let kbps: u64 = value.split_whitespace().next()?.parse().ok()?;
+    Some(kbps * 1000)
+}
+
+/// Channel count from a layout name such as "stereo", "5.1(side)" or "3 channels".
+fn parse_channels(layout: &str) -> Option<u8> {
+    let layout = layout.split('(').next()?.trim();
+    if let Some(count) = layout.strip_suffix(" channels") {
+        return count.parse().ok();
+    }
+
+    let channels = match layout {
+        "mono" => 1,
+        "stereo" | "downmix" => 2,
+        "2.1" | "3.0" => 3,
+        "quad" | "4.0" | "3.1" => 4,
+        "5.0" | "4.1" => 5,
+        "5.1" | "6.0" | "hexagonal" => 6,
+        "6.1" | "7.0" => 7,
+        "7.1" | "octagonal" => 8,
+        _ => return None,
+    };
+
+    Some(channels)
+}
+
+/// Parse "HH:MM:SS.xx" into a duration.
+pub fn parse_timestamp(timestamp: &str) -> Option<Duration> {
+    let mut parts = timestamp.splitn(3, ':');
+    let hours: f64 = parts.next()?.parse().ok()?;
+    let minutes: f64 = parts.next()?.parse().ok()?;
+    let seconds: f64 = parts.next()?.parse().ok()?;
+
+    Duration::try_from_secs_f64(hours * 3600.0 + minutes * 60.0 + seconds).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_video_info() {
+        let stderr = "\
+Input #0, mov,mp4,m4a,3gp,3g2,mj2, from 'a.mp4':
+  Metadata:
+    major_brand     : isom
+    location        : +52.3740+004.8897/
+    copyright       : (c) 2024 Jane
+  Duration: 00:01:02.50, start: 0.000000, bitrate: 1205 kb/s
+  Stream #0:0[0x1](und): Video: h264 (High) (avc1 / 0x31637661), yuv420p(tv, bt709, progressive), 1920x1080 [SAR 1:1 DAR 16:9], 1069 kb/s, 29.97 fps, 29.97 tbr, 30k tbn (default)
+    Metadata:
+      handler_name    : Core Media Video
+  Stream #0:1[0x2](und): Audio: aac (LC) (mp4a / 0x6134706D), 48000 Hz, 5.1(side), fltp, 128 kb/s (default)
+At least one output file must be specified
+";
+        let info = parse_media_info(stderr);
+
+        assert_eq!(info.duration, Some(Duration::from_millis(62_500)));
+        assert_eq!(info.bitrate, Some(1_205_000));
+        assert_eq!(info.video_codec.as_deref(), Some("h264"));
+        assert_eq!((info.width, info.height), (Some(1920), Some(1080)));
+        assert_eq!(info.fps, Some(29.97));
+        assert_eq!(info.video_bitrate, Some(1_069_000));
+        assert_eq!(info.audio_codec.as_deref(), Some("aac"));
+        assert_eq!(info.sample_rate, Some(48000));
+        assert_eq!(info.channels, Some(6));
+        assert_eq!(info.audio_bitrate, Some(128_000));
+        // Stream tags aren't the input's
+        assert_eq!(
+            info.tags.keys().collect::<Vec<_>>(),
+            vec!["copyright", "location", "major_brand"]
+        );
+        assert_eq!(info.tags["location"], "+52.3740+004.8897/");
+    }
+
+    #[test]
+    fn test_parse_rotated_video() {
+        // A portrait phone video is stored landscape, with the rotation as side data
+        let side_data = "\
+Input #0, mov,mp4,m4a,3gp,3g2,mj2, from 'portrait.mov':
+  Duration: 00:00:10.00, start: 0.000000, bitrate: 8000 kb/s
+  Stream #0:0[0x1](und): Video: hevc (Main) (hvc1 / 0x31637668), yuv420p(tv, bt709), 1920x1080, 7900 kb/s, 30 fps, 30 tbr, 600 tbn (default)
+    Metadata:
+      handler_name    : Core Media Video
+    Side data:
+      displaymatrix: rotation of -90.00 degrees
+  Stream #0:1[0x2](und): Audio: aac (LC) (mp4a / 0x6134706D), 44100 Hz, mono, fltp, 96 kb/s (default)
+";
+        let info = parse_media_info(side_data);
+        assert_eq!((info.width, info.height), (Some(1080), Some(1920)));
+
+        let tag = side_data.replace("displaymatrix: rotation of -90.00 degrees", "rotate : 270");
+        let info = parse_media_info(&tag);
+        assert_eq!((info.width, info.height), (Some(1080), Some(1920)));
+
+        // Upside down keeps its size
+        let flipped = side_data.replace("-90.00", "180.00");
+        let info = parse_media_info(&flipped);
+        assert_eq!((info.width, info.height), (Some(1920), Some(1080)));
+    }
+
+    #[test]
+    fn test_parse_image_and_audio_info() {
+        let image = "\
+Input #0, png_pipe, from 'a.png':
+  Duration: N/A, bitrate: N/A
+  Stream #0:0: Video: png, rgba(pc), 640x480, 25 tbr, 25 tbn
+";
+        let info = parse_media_info(image);
+        assert_eq!(info.duration, None);
+        assert_eq!(info.bitrate, None);
+        assert_eq!((info.width, info.height), (Some(640), Some(480)));
+        assert_eq!(info.video_codec.as_deref(), Some("png"));
+        assert_eq!(info.audio_codec, None);
+
+        // The cover art of an mp3 isn't a video stream
+        let audio = "\
+Input #0, mp3, from 'a.mp3':
+  Duration: 00:03:00.05, start: 0.025057, bitrate: 320 kb/s
+  Stream #0:0: Audio: mp3 (mp3float), 44100 Hz, stereo, fltp, 320 kb/s
+  Stream #0:1: Video: mjpeg (Baseline), yuvj420p(pc, bt470bg/unknown/unknown), 500x500 [SAR 1:1 DAR 1:1], 90k tbr, 90k tbn (attached pic)
+";
+        let info = parse_media_info(audio);
+        assert_eq!(info.video_codec, None);
+        assert_eq!(info.audio_codec.as_deref(), Some("mp3"));
+        assert_eq!(info.channels, Some(2));
+        assert_eq!(info.sample_rate, Some(44100));
+
+        assert_eq!(parse_media_info("no input info"), MediaInfo::default());
+    }
+}
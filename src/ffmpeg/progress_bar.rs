@@ -13,3 +13,19 @@ pub fn init_progress_bar(len: u64) -> ProgressBar {
     pb.enable_steady_tick(Duration::from_millis(100));
     pb
 }
+
+/// A child bar for one in-flight encode, measured in milliseconds of media.
+///
+/// The length is set once the input duration is known; `{msg}` carries fps and speed.
+pub fn init_file_bar(name: &str) -> ProgressBar {
+    let pb = ProgressBar::no_length();
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template("  {prefix:30!} [{bar:30.green/white}] {percent:>3}% {msg} ETA {eta}")
+            .unwrap()
+            .progress_chars("=> "),
+    );
+    pb.set_prefix(name.to_string());
+    pb.enable_steady_tick(Duration::from_millis(100));
+    pb
+}
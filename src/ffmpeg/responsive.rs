@@ -0,0 +1,271 @@
+use super::probe::MediaInfo;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// What `--srcset` writes next to each image set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SrcsetFormat {
+    /// `hero.srcset.html`, an `<img>` tag to paste into a page
+    Html,
+    /// `hero.srcset.json`, for build tooling
+    Json,
+}
+
+/// `--responsive`: export every image at several widths.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Responsive {
+    /// Ascending, without duplicates
+    pub widths: Vec<u32>,
+    pub srcset: Option<SrcsetFormat>,
+}
+
+/// One written image of a set.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Variant {
+    #[serde(serialize_with = "serialize_file_name")]
+    pub path: PathBuf,
+    pub width: u32,
+    pub height: Option<u32>,
+}
+
+impl Responsive {
+    pub fn new(mut widths: Vec<u32>, srcset: Option<SrcsetFormat>) -> Self {
+        widths.sort_unstable();
+        widths.dedup();
+        Self { widths, srcset }
+    }
+
+    /// The widths to export for an input: those no wider than the source. A source
+    /// narrower than every width is exported once, at its own width.
+    pub fn ladder(&self, info: &MediaInfo) -> Vec<u32> {
+        let Some(source_width) = info.width else {
+            return self.widths.clone();
+        };
+
+        let widths: Vec<u32> = self
+            .widths
+            .iter()
+            .copied()
+            .filter(|width| *width <= source_width)
+            .collect();
+
+        if widths.is_empty() {
+            vec![source_width]
+        } else {
+            widths
+        }
+    }
+
+    /// The height `width` scales `info` to, when the source size is known.
+    pub fn height_for(info: &MediaInfo, width: u32) -> Option<u32> {
+        let (source_width, source_height) = (info.width?, info.height?);
+        if source_width == 0 {
+            return None;
+        }
+        let height = (source_height as f64 * width as f64 / source_width as f64).round() as u32;
+        Some(height.max(1))
+    }
+
+    /// Write the srcset snippet or manifest for `sets` next to `output` (the output
+    /// path of the input as a single image). There is one set per format, most preferred
+    /// first. Returns its path.
+    pub fn write_srcset(
+        &self,
+        input: &Path,
+        output: &Path,
+        sets: &[Vec<Variant>],
+    ) -> Result<Option<PathBuf>> {
+        let Some(format) = self.srcset else {
+            return Ok(None);
+        };
+
+        let (extension, content) = match format {
+            SrcsetFormat::Html => ("srcset.html", srcset_html(sets)),
+            SrcsetFormat::Json => ("srcset.json", srcset_json(input, &sets.concat())?),
+        };
+        let path = output.with_extension(extension);
+        fs::write(&path, content).with_context(|| format!("Failed to write {}", path.display()))?;
+
+        Ok(Some(path))
+    }
+}
+
+/// e.g. `<img src="hero-1280w.webp" srcset="hero-640w.webp 640w, hero-1280w.webp 1280w" ...>`
+///
+/// With several formats the `<img>` gets the last one, wrapped in a `<picture>` with a
+/// `<source>` for each of the others, so browsers pick the first format they support.
+fn srcset_html(sets: &[Vec<Variant>]) -> String {
+    let Some((fallback, sources)) = sets.split_last() else {
+        return String::new();
+    };
+    if sources.is_empty() {
+        return img_tag(fallback);
+    }
+
+    let mut picture = "<picture>\n".to_string();
+    for variants in sources {
+        let mime = variants
+            .first()
+            .and_then(|variant| mime_type(&variant.path))
+            .map(|mime| format!(" type=\"{}\"", mime))
+            .unwrap_or_default();
+        picture.push_str(&format!(
+            "  <source{} srcset=\"{}\" sizes=\"100vw\">\n",
+            mime,
+            srcset(variants)
+        ));
+    }
+    picture.push_str(&format!("  {}</picture>\n", img_tag(fallback)));
+
+    picture
+}
+
+fn img_tag(variants: &[Variant]) -> String {
+    // The largest image is the fallback for browsers without srcset support
+    let Some(largest) = variants.last() else {
+        return String::new();
+    };
+    let mut tag = format!(
+        "<img src=\"{}\" srcset=\"{}\" sizes=\"100vw\"",
+        file_name(&largest.path),
+        srcset(variants)
+    );
+    // Lets the browser reserve the space before the image loads
+    if let Some(height) = largest.height {
+        tag.push_str(&format!(
+            " width=\"{}\" height=\"{}\"",
+            largest.width, height
+        ));
+    }
+    tag.push_str(" alt=\"\">\n");
+
+    tag
+}
+
+fn srcset(variants: &[Variant]) -> String {
+    let candidates: Vec<String> = variants
+        .iter()
+        .map(|variant| format!("{} {}w", file_name(&variant.path), variant.width))
+        .collect();
+    candidates.join(", ")
+}
+
+fn mime_type(path: &Path) -> Option<&'static str> {
+    let mime = match path.extension()?.to_str()?.to_lowercase().as_str() {
+        "avif" => "image/avif",
+        "webp" => "image/webp",
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        _ => return None,
+    };
+    Some(mime)
+}
+
+fn srcset_json(input: &Path, variants: &[Variant]) -> Result<String> {
+    #[derive(Serialize)]
+    struct SrcsetManifest<'a> {
+        source: &'a Path,
+        images: &'a [Variant],
+    }
+
+    let manifest = SrcsetManifest {
+        source: input,
+        images: variants,
+    };
+    Ok(serde_json::to_string_pretty(&manifest)? + "\n")
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Variants sit next to the srcset file, so only their file names are written.
+fn serialize_file_name<S: serde::Serializer>(
+    path: &Path,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&file_name(path))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn info(width: u32, height: u32) -> MediaInfo {
+        MediaInfo {
+            width: Some(width),
+            height: Some(height),
+            ..MediaInfo::default()
+        }
+    }
+
+    #[test]
+    fn test_ladder() {
+        let responsive = Responsive::new(vec![1920, 320, 640, 1280, 640], None);
+        assert_eq!(responsive.widths, vec![320, 640, 1280, 1920]);
+
+        assert_eq!(responsive.ladder(&info(1500, 1000)), vec![320, 640, 1280]);
+        assert_eq!(
+            responsive.ladder(&info(1920, 1080)),
+            vec![320, 640, 1280, 1920]
+        );
+        // Smaller than every width, exported once as it is
+        assert_eq!(responsive.ladder(&info(200, 100)), vec![200]);
+        // Unknown size, every width is tried and ffmpeg won't upscale
+        assert_eq!(
+            responsive.ladder(&MediaInfo::default()),
+            vec![320, 640, 1280, 1920]
+        );
+
+        assert_eq!(Responsive::height_for(&info(1500, 1000), 640), Some(427));
+        assert_eq!(Responsive::height_for(&MediaInfo::default(), 640), None);
+    }
+
+    #[test]
+    fn test_srcset() {
+        let variants = vec![
+            Variant {
+                path: PathBuf::from("out/hero-320w.webp"),
+                width: 320,
+                height: Some(180),
+            },
+            Variant {
+                path: PathBuf::from("out/hero-640w.webp"),
+                width: 640,
+                height: Some(360),
+            },
+        ];
+
+        assert_eq!(
+            srcset_html(std::slice::from_ref(&variants)),
+            "<img src=\"hero-640w.webp\" srcset=\"hero-320w.webp 320w, hero-640w.webp 640w\" \
+             sizes=\"100vw\" width=\"640\" height=\"360\" alt=\"\">\n"
+        );
+
+        let avif: Vec<Variant> = variants
+            .iter()
+            .map(|variant| Variant {
+                path: variant.path.with_extension("avif"),
+                ..variant.clone()
+            })
+            .collect();
+        assert_eq!(
+            srcset_html(&[avif, variants.clone()]),
+            "<picture>\n  \
+             <source type=\"image/avif\" srcset=\"hero-320w.avif 320w, hero-640w.avif 640w\" sizes=\"100vw\">\n  \
+             <img src=\"hero-640w.webp\" srcset=\"hero-320w.webp 320w, hero-640w.webp 640w\" \
+             sizes=\"100vw\" width=\"640\" height=\"360\" alt=\"\">\n</picture>\n"
+        );
+
+        let json: serde_json::Value =
+            serde_json::from_str(&srcset_json(Path::new("in/hero.jpg"), &variants).unwrap())
+                .unwrap();
+        assert_eq!(json["source"], "in/hero.jpg");
+        assert_eq!(json["images"][1]["path"], "hero-640w.webp");
+        assert_eq!(json["images"][1]["width"], 640);
+    }
+}
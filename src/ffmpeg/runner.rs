@@ -0,0 +1,157 @@
+use super::cancel::ChildGuard;
+use anyhow::{Context, Result};
+use indicatif::ProgressBar;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+use std::thread;
+use std::time::Duration;
+
+/// The fields crunch cares about from one `-progress` block.
+#[derive(Debug, Default, PartialEq)]
+struct ProgressUpdate {
+    out_time: Option<Duration>,
+    fps: Option<String>,
+    speed: Option<String>,
+    end: bool,
+}
+
+impl ProgressUpdate {
+    /// Apply one `key=value` line. Returns true when the line closes a block.
+    fn apply(&mut self, line: &str) -> bool {
+        let Some((key, value)) = line.trim().split_once('=') else {
+            return false;
+        };
+
+        match key {
+            "out_time_us" => {
+                self.out_time = value.parse::<u64>().ok().map(Duration::from_micros);
+            }
+            "fps" => self.fps = Some(value.to_string()),
+            "speed" => self.speed = Some(value.trim().to_string()),
+            "progress" => {
+                self.end = value == "end";
+                return true;
+            }
+            _ => {}
+        }
+
+        false
+    }
+
+    fn message(&self) -> String {
+        let fps = self.fps.as_deref().unwrap_or("0");
+        let speed = self.speed.as_deref().unwrap_or("N/A");
+        format!("{} fps, {}", fps, speed)
+    }
+}
+
+/// Run ffmpeg with `args` and collect its output, like `Command::output` but tracked
+/// so Ctrl-C can terminate it.
+pub fn run_ffmpeg(ffmpeg: &Path, args: &[String]) -> Result<Output> {
+    let child = Command::new(ffmpeg)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to execute ffmpeg")?;
+
+    let _guard = ChildGuard::register(child.id());
+    child
+        .wait_with_output()
+        .context("Failed to wait for ffmpeg")
+}
+
+/// Run ffmpeg with `args`, reporting progress on `bar`.
+///
+/// `-progress pipe:1` is added so ffmpeg writes machine-readable progress blocks to stdout.
+/// When `duration` is known the bar is sized to it in milliseconds, otherwise it just spins.
+pub fn run_ffmpeg_with_progress(
+    ffmpeg: &Path,
+    args: &[String],
+    duration: Option<Duration>,
+    bar: &ProgressBar,
+) -> Result<Output> {
+    if let Some(duration) = duration {
+        bar.set_length(duration.as_millis() as u64);
+    }
+
+    let mut child = Command::new(ffmpeg)
+        .args(["-nostats", "-progress", "pipe:1"])
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to execute ffmpeg")?;
+    let _guard = ChildGuard::register(child.id());
+
+    // Drain stderr on its own thread so a chatty ffmpeg can't block on a full pipe
+    let mut stderr = child
+        .stderr
+        .take()
+        .context("Failed to capture ffmpeg stderr")?;
+    let stderr_reader = thread::spawn(move || {
+        let mut buf = Vec::new();
+        let _ = stderr.read_to_end(&mut buf);
+        buf
+    });
+
+    let stdout = child
+        .stdout
+        .take()
+        .context("Failed to capture ffmpeg stdout")?;
+    let mut update = ProgressUpdate::default();
+    for line in BufReader::new(stdout).lines() {
+        let Ok(line) = line else { break };
+        if !update.apply(&line) {
+            continue;
+        }
+
+        if let Some(out_time) = update.out_time {
+            bar.set_position(out_time.as_millis() as u64);
+        }
+        bar.set_message(update.message());
+
+        if update.end
+            && let Some(len) = bar.length()
+        {
+            bar.set_position(len);
+        }
+    }
+
+    let status = child.wait().context("Failed to wait for ffmpeg")?;
+    let stderr = stderr_reader.join().unwrap_or_default();
+
+    Ok(Output {
+        status,
+        stdout: Vec::new(),
+        stderr,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_progress_update() {
+        let mut update = ProgressUpdate::default();
+        assert!(!update.apply("fps=24.5"));
+        assert!(!update.apply("out_time_us=1500000"));
+        assert!(!update.apply("speed=2.01x"));
+        assert!(update.apply("progress=continue"));
+
+        assert_eq!(update.out_time, Some(Duration::from_millis(1500)));
+        assert_eq!(update.message(), "24.5 fps, 2.01x");
+        assert!(!update.end);
+
+        // ffmpeg reports N/A before the first frame is written
+        update.apply("out_time_us=N/A");
+        assert_eq!(update.out_time, None);
+
+        assert!(update.apply("progress=end"));
+        assert!(update.end);
+    }
+}
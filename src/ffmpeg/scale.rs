@@ -0,0 +1,155 @@
+use super::probe::MediaInfo;
+use std::collections::BTreeMap;
+
+/// Downscaling limits from `--max-width`, `--max-height` and `--scale`.
+///
+/// Outputs keep their aspect ratio and are never upscaled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Resize {
+    pub max_width: Option<u32>,
+    pub max_height: Option<u32>,
+    /// Percentage of the original size, 1-100
+    pub scale: Option<u8>,
+}
+
+impl Resize {
+    pub fn is_none(&self) -> bool {
+        self.max_width.is_none() && self.max_height.is_none() && self.scale.is_none()
+    }
+
+    /// The output size for a `width`x`height` input, or `None` when it already fits.
+    /// `even` rounds down to even dimensions, as yuv420p video requires.
+    pub fn target_size(&self, width: u32, height: u32, even: bool) -> Option<(u32, u32)> {
+        if width == 0 || height == 0 {
+            return None;
+        }
+
+        let mut factor = self.scale.map_or(1.0, |scale| scale as f64 / 100.0);
+        if let Some(max_width) = self.max_width {
+            factor = factor.min(max_width as f64 / width as f64);
+        }
+        if let Some(max_height) = self.max_height {
+            factor = factor.min(max_height as f64 / height as f64);
+        }
+        if factor >= 1.0 {
+            return None;
+        }
+
+        let fit = |size: u32| {
+            let scaled = ((size as f64 * factor).round() as u32).max(1);
+            if even { (scaled & !1).max(2) } else { scaled }
+        };
+
+        Some((fit(width), fit(height)))
+    }
+
+    /// The `-vf` scale filter for an input, using resampler `flags` (e.g. "lanczos"),
+    /// or `None` when nothing needs scaling.
+    ///
+    /// With the input size known the exact size is computed, otherwise ffmpeg is left
+    /// to work it out from the input.
+    pub fn filter(&self, info: &MediaInfo, even: bool, flags: &str) -> Option<String> {
+        if self.is_none() {
+            return None;
+        }
+
+        if let (Some(width), Some(height)) = (info.width, info.height) {
+            let (width, height) = self.target_size(width, height, even)?;
+            return Some(format!("scale={}:{}:flags={}", width, height, flags));
+        }
+
+        // min() against the input size keeps ffmpeg from upscaling
+        let factor = self
+            .scale
+            .map_or("1".to_string(), |scale| format!("{}/100", scale));
+        let limit = |dimension: &str, max: Option<u32>| match max {
+            Some(max) => format!("'min({}*{},{})'", dimension, factor, max),
+            None => format!("'{}*{}'", dimension, factor),
+        };
+        let mut filter = format!(
+            "scale=w={}:h={}:force_original_aspect_ratio=decrease",
+            limit("iw", self.max_width),
+            limit("ih", self.max_height)
+        );
+        if even {
+            filter.push_str(":force_divisible_by=2");
+        }
+        filter.push_str(&format!(":flags={}", flags));
+
+        Some(filter)
+    }
+
+    /// The limits as name/value pairs, for reports and the manifest.
+    pub fn settings(&self) -> BTreeMap<String, String> {
+        let mut settings = BTreeMap::new();
+
+        if let Some(max_width) = self.max_width {
+            settings.insert("max_width".to_string(), max_width.to_string());
+        }
+        if let Some(max_height) = self.max_height {
+            settings.insert("max_height".to_string(), max_height.to_string());
+        }
+        if let Some(scale) = self.scale {
+            settings.insert("scale".to_string(), scale.to_string());
+        }
+
+        settings
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_target_size() {
+        let cap = Resize {
+            max_width: Some(2560),
+            max_height: Some(1080),
+            scale: None,
+        };
+        // Height is the tighter limit
+        assert_eq!(cap.target_size(3840, 2160, false), Some((1920, 1080)));
+        // Already fits, never upscaled
+        assert_eq!(cap.target_size(1280, 720, false), None);
+
+        let half = Resize {
+            scale: Some(50),
+            ..Resize::default()
+        };
+        assert_eq!(half.target_size(1001, 667, false), Some((501, 334)));
+        // Rounded down to even for yuv420p
+        assert_eq!(half.target_size(1001, 667, true), Some((500, 334)));
+        assert_eq!(half.target_size(3, 3, true), Some((2, 2)));
+
+        assert_eq!(Resize::default().target_size(3840, 2160, false), None);
+    }
+
+    #[test]
+    fn test_filter() {
+        let resize = Resize {
+            max_width: Some(1920),
+            max_height: None,
+            scale: Some(50),
+        };
+        let known = MediaInfo {
+            width: Some(1920),
+            height: Some(1080),
+            ..MediaInfo::default()
+        };
+
+        assert_eq!(
+            resize.filter(&known, true, "bicubic").as_deref(),
+            Some("scale=960:540:flags=bicubic")
+        );
+        assert_eq!(
+            resize
+                .filter(&MediaInfo::default(), true, "lanczos")
+                .as_deref(),
+            Some(
+                "scale=w='min(iw*50/100,1920)':h='ih*50/100':force_original_aspect_ratio=decrease:force_divisible_by=2:flags=lanczos"
+            )
+        );
+        assert_eq!(Resize::default().filter(&known, false, "lanczos"), None);
+    }
+}
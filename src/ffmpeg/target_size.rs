@@ -0,0 +1,112 @@
+use anyhow::{Result, bail};
+use std::time::Duration;
+
+/// Share of the file taken by the container rather than the streams: headers, the
+/// index and per-packet framing.
+const MUX_OVERHEAD: f64 = 0.03;
+
+/// Below this many bits per second there is no recognisable picture left.
+const MIN_VIDEO_BITRATE: u64 = 50_000;
+
+/// How much further below the target a retry aims than the overshoot alone suggests.
+const RETRY_MARGIN: f64 = 0.95;
+
+/// A `--target-size` every video output has to fit in, in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TargetSize(u64);
+
+impl TargetSize {
+    /// e.g. "8M", "500k", "1.5G" or "750000". The units are powers of 1024, as in the
+    /// summary, and may be followed by "B" or "iB" ("8MB", "8MiB").
+    pub fn parse(value: &str) -> Result<Self> {
+        let lower = value.trim().to_lowercase();
+        let number = lower
+            .strip_suffix("ib")
+            .or_else(|| lower.strip_suffix('b'))
+            .unwrap_or(&lower);
+        let (number, multiplier) = match number.char_indices().last() {
+            Some((i, 'k')) => (&number[..i], 1u64 << 10),
+            Some((i, 'm')) => (&number[..i], 1 << 20),
+            Some((i, 'g')) => (&number[..i], 1 << 30),
+            _ => (number, 1),
+        };
+
+        match number.trim().parse::<f64>() {
+            Ok(size) if size > 0.0 => Ok(Self((size * multiplier as f64) as u64)),
+            _ => bail!("Invalid size '{}', use e.g. 8M, 500K or 1.5G", value),
+        }
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.0
+    }
+
+    /// The video bitrate (bits per second) that fills the target over `duration`, after
+    /// `audio_bitrate` and the container's share.
+    pub fn video_bitrate(&self, duration: Duration, audio_bitrate: u64) -> Result<u64> {
+        let seconds = duration.as_secs_f64();
+        if seconds <= 0.0 {
+            bail!("A video without a duration can't be fitted to a size");
+        }
+
+        let total = self.0 as f64 * 8.0 * (1.0 - MUX_OVERHEAD) / seconds;
+        let video = total - audio_bitrate as f64;
+        if video < MIN_VIDEO_BITRATE as f64 {
+            bail!(
+                "{:.0}s of video don't fit in {}: that leaves {} kb/s for the picture after {} kb/s of audio",
+                seconds,
+                crate::summary::format_size(self.0),
+                (video.max(0.0) / 1000.0) as u64,
+                audio_bitrate / 1000
+            );
+        }
+
+        Ok(video as u64)
+    }
+
+    /// The bitrate to retry with after `bitrate` produced `size` bytes, over the target.
+    /// `None` when that would go below what's still watchable.
+    pub fn retry_bitrate(&self, bitrate: u64, size: u64) -> Option<u64> {
+        let scaled = bitrate as f64 * self.0 as f64 / size as f64 * RETRY_MARGIN;
+        (scaled >= MIN_VIDEO_BITRATE as f64).then_some(scaled as u64)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(TargetSize::parse("8M").unwrap().bytes(), 8 * 1024 * 1024);
+        assert_eq!(TargetSize::parse("8mb").unwrap().bytes(), 8 * 1024 * 1024);
+        assert_eq!(TargetSize::parse("500KiB").unwrap().bytes(), 500 * 1024);
+        assert_eq!(TargetSize::parse("1.5G").unwrap().bytes(), 3 << 29);
+        assert_eq!(TargetSize::parse("750000").unwrap().bytes(), 750_000);
+        assert!(TargetSize::parse("0M").is_err());
+        assert!(TargetSize::parse("big").is_err());
+    }
+
+    #[test]
+    fn test_video_bitrate() {
+        let target = TargetSize::parse("8M").unwrap();
+
+        // 8 MiB over 60s is ~1118 kb/s, minus 3% for the container and 128k of audio
+        let bitrate = target
+            .video_bitrate(Duration::from_secs(60), 128_000)
+            .unwrap();
+        assert_eq!(bitrate / 1000, 956);
+
+        // An hour doesn't fit
+        assert!(
+            target
+                .video_bitrate(Duration::from_secs(3600), 128_000)
+                .is_err()
+        );
+
+        // 10% over, the retry aims 5% under the scaled bitrate
+        let size = target.bytes() * 11 / 10;
+        assert_eq!(target.retry_bitrate(1_000_000, size).unwrap() / 1000, 863);
+        assert_eq!(target.retry_bitrate(60_000, target.bytes() * 2), None);
+    }
+}
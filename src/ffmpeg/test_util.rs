@@ -0,0 +1,63 @@
+//! Builders for the image bytes the metadata, orientation and animation tests parse.
+
+/// A JPEG segment: the marker, the big endian length (which counts itself), then `payload`.
+pub fn jpeg_segment(marker: u8, payload: &[u8]) -> Vec<u8> {
+    let mut segment = vec![0xFF, marker];
+    segment.extend(((payload.len() + 2) as u16).to_be_bytes());
+    segment.extend(payload);
+    segment
+}
+
+/// A PNG chunk. The CRC is left zero, nothing here checks it.
+pub fn png_chunk(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+    let mut chunk = (payload.len() as u32).to_be_bytes().to_vec();
+    chunk.extend(kind);
+    chunk.extend(payload);
+    chunk.extend([0; 4]);
+    chunk
+}
+
+/// The payload of an APP1 "Exif" segment: a TIFF with one IFD holding the make ("Cam")
+/// and then `orientation`.
+pub fn exif(orientation: u16, big_endian: bool) -> Vec<u8> {
+    let u16_bytes = |value: u16| {
+        if big_endian {
+            value.to_be_bytes()
+        } else {
+            value.to_le_bytes()
+        }
+    };
+    let u32_bytes = |value: u32| {
+        if big_endian {
+            value.to_be_bytes()
+        } else {
+            value.to_le_bytes()
+        }
+    };
+
+    let mut exif = b"Exif\0\0".to_vec();
+    exif.extend(if big_endian { b"MM" } else { b"II" });
+    exif.extend(u16_bytes(42));
+    exif.extend(u32_bytes(8));
+    exif.extend(u16_bytes(2));
+    exif.extend(u16_bytes(0x010F)); // Make
+    exif.extend(u16_bytes(2)); // ASCII
+    exif.extend(u32_bytes(4));
+    exif.extend(b"Cam\0");
+    exif.extend(u16_bytes(0x0112)); // Orientation
+    exif.extend(u16_bytes(3)); // SHORT
+    exif.extend(u32_bytes(1));
+    exif.extend(u16_bytes(orientation));
+    exif.extend([0, 0]);
+    exif.extend(u32_bytes(0));
+    exif
+}
+
+/// A JPEG with a JFIF segment, an EXIF segment holding `orientation`, then an empty scan.
+pub fn exif_jpeg(orientation: u16, big_endian: bool) -> Vec<u8> {
+    let mut jpeg = vec![0xFF, 0xD8];
+    jpeg.extend(jpeg_segment(0xE0, b"JFIF\0\x01\x01\0\0\x01\0\x01\0\0"));
+    jpeg.extend(jpeg_segment(0xE1, &exif(orientation, big_endian)));
+    jpeg.extend([0xFF, 0xDA, 0x00, 0x02, 0xFF, 0xD9]);
+    jpeg
+}
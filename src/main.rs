@@ -1,14 +1,25 @@
 mod consts;
 mod ffmpeg;
+mod manifest;
+mod report;
+mod summary;
 mod utilities;
 
 use anyhow::Result;
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use ffmpeg::{
-    AudioCompressOptions, BaseCompressOptions, ImageCompressOptions, VideoCompressOptions,
-    compress_all_audios, compress_all_images, compress_all_videos, get_ffmpeg,
+    AnimatedOutput, AudioCompressOptions, BaseCompressOptions, CANCELLED_EXIT_CODE, Capabilities,
+    ImageCompressOptions, MetadataPolicy, NoGainAction, PosterAt, PreviewFormat, PreviewOptions,
+    Resize, Responsive, SrcsetFormat, TargetSize, VideoCompressOptions, cache_dir, clean_cache,
+    compress_all_audios, compress_all_images, compress_all_videos, extract_all_previews,
+    get_ffmpeg, install_cancel_handler, is_cancelled,
 };
+use manifest::Manifest;
+use report::Report;
 use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+use summary::{FileRecord, MediaKind, Summary, format_size};
 use utilities::{get_audio_files, get_image_files, get_video_files};
 
 #[derive(Parser, Debug)]
@@ -28,19 +39,44 @@ EXAMPLES:
     crunch --videos                      Compress videos only (default: webm)
     crunch --videos=webm                 Compress videos to webm
     crunch --images=webp                 Compress images to webp
+    crunch --images=avif,webp,jpg        Compress every image to avif, webp and jpg
     crunch --audio=mp3                   Compress audios to mp3
+    crunch --images --report=out.json    Also write a JSON report of every file
+    crunch --images --never-grow=copy    Keep the original when re-encoding doesn't make it smaller
+    crunch --default --incremental       Only compress files that are new or changed since the last run
+    crunch --videos --resume             Continue a batch that was interrupted
+    crunch --images --max-width=2560     Scale images down to at most 2560px wide
+    crunch --videos --max-height=1080    Scale videos down to at most 1080p
+    crunch --videos=mp4 --target-size=8M Fit every video in 8 MB, for email and chat uploads
+    crunch --images --animated=mp4       Turn animated GIFs into muted looping mp4 videos
+    crunch --images --metadata=strip     Drop all metadata, including color profiles
+    crunch --poster --contact-sheet --preview
+                                         Extract a poster frame, a 3x3 contact sheet and a GIF preview of every video
+    crunch --poster=0:05                 Take the poster frame 5 seconds in
+    crunch --images --responsive --srcset=html
+                                         Export every image at 320/640/1280/1920px wide, plus an <img srcset> tag
+    crunch --videos --ffmpeg=/opt/ffmpeg/bin/ffmpeg
+                                         Encode with a specific ffmpeg build
+    crunch cache clean                   Delete the extracted embedded ffmpeg
 
 SUPPORTED FORMATS:
     Videos: webm, mp4, mkv, av1, etc.
     Images: webp, avif, jpg, png, etc.
     Audio:  opus, mp3, aac, flac, etc.
 
+FFMPEG:
+    crunch uses --ffmpeg if given, then $CRUNCH_FFMPEG, then ffmpeg on PATH,
+    and falls back to its embedded ffmpeg, which is extracted once to the
+    user cache directory.
+
 MORE INFO:
     https://github.com/byteoxo/crunch",
     arg_required_else_help = true
 )]
+// --target-size needs videos to compress, from either flag
+#[command(group(clap::ArgGroup::new("video_inputs").args(["videos", "default"]).multiple(true)))]
 struct Args {
-    /// Use default settings (videos=webm, images=webp)
+    /// Use default settings (videos=webm, images=webp, audios=mp3)
     #[arg(long)]
     default: bool,
 
@@ -51,8 +87,8 @@ struct Args {
     #[arg(short = 'o', long, num_args = 0..=1, default_value = "./", default_missing_value = "./")]
     output_path: PathBuf,
 
-    /// Compress leve
-    #[arg(long, num_args = 0..=1, default_value="midium", default_missing_value="midium")]
+    /// Compress level: low, medium or high
+    #[arg(long, num_args = 0..=1, default_value="medium", default_missing_value="medium")]
     level: String,
 
     #[arg(long, num_args= 0..=1, default_missing_value = "compressed")]
@@ -62,45 +98,224 @@ struct Args {
     #[arg(long, num_args = 0..=1, default_missing_value = "webm")]
     videos: Option<String>,
 
-    /// Image format. Use --images for default(webp) or --images=FORMAT
+    /// Image format. Use --images for default(webp), --images=FORMAT, or --images=FORMAT,FORMAT,... for several
     #[arg(long, num_args = 0..=1, default_missing_value = "webp")]
     images: Option<String>,
 
     /// Audios format. Use --audios for default(webp) or --audios=FORMAT
     #[arg(long, num_args = 0..=1, default_missing_value = "mp3")]
     audios: Option<String>,
+
+    /// Don't keep outputs that aren't smaller than their source: skip them, or copy the original through.
+    /// Copies follow --metadata: JPEG, PNG and WebP images are rewritten without the dropped
+    /// metadata, videos and audio are remuxed without it, other images are skipped unless it's keep
+    #[arg(long, num_args = 0..=1, default_missing_value = "skip", value_name = "ACTION", value_parser = ["skip", "copy"])]
+    never_grow: Option<String>,
+
+    /// With --never-grow, how much smaller (in percent) an output must be to be kept
+    #[arg(long, default_value_t = 0, value_name = "PERCENT", value_parser = clap::value_parser!(u8).range(0..=100))]
+    min_saving: u8,
+
+    /// Scale images and videos down to at most this many pixels wide, keeping the aspect ratio
+    #[arg(long, value_name = "PX", value_parser = clap::value_parser!(u32).range(2..))]
+    max_width: Option<u32>,
+
+    /// Scale images and videos down to at most this many pixels high, keeping the aspect ratio
+    #[arg(long, value_name = "PX", value_parser = clap::value_parser!(u32).range(2..))]
+    max_height: Option<u32>,
+
+    /// Scale images and videos to this percentage of their size. Never upscales
+    #[arg(long, value_name = "PERCENT", value_parser = clap::value_parser!(u8).range(1..=100))]
+    scale: Option<u8>,
+
+    /// Export each image at several widths (default: 320,640,1280,1920), named like hero-640w.webp.
+    /// Widths wider than the source are skipped
+    #[arg(long, value_name = "WIDTHS", num_args = 0..=1, value_delimiter = ',', default_missing_value = "320,640,1280,1920", value_parser = clap::value_parser!(u32).range(1..), conflicts_with_all = ["max_width", "max_height", "scale"])]
+    responsive: Option<Vec<u32>>,
+
+    /// With --responsive, also write an HTML srcset snippet or a JSON manifest next to each set
+    #[arg(long, value_name = "FORMAT", value_parser = ["html", "json"], requires = "responsive")]
+    srcset: Option<String>,
+
+    /// What animated GIFs and APNGs become: an animation in the --images format, keeping its
+    /// loop count, or a muted looping mp4/webm video
+    #[arg(long, default_value = "image", value_name = "OUTPUT", value_parser = ["image", "mp4", "webm"])]
+    animated: String,
+
+    /// Metadata outputs keep: strip drops it all, keep copies it all, safe drops location and
+    /// device identifiers but keeps descriptive tags (title, copyright...), chapters and color profiles
+    /// (ffmpeg writes WebP without one, so those are lost in WebP outputs).
+    /// Originals copied through by --never-grow=copy are covered too
+    #[arg(long, default_value = "safe", value_name = "POLICY", value_parser = ["strip", "keep", "safe"])]
+    metadata: String,
+
+    /// Extract a poster frame of every video, at the first scene change or at a time
+    /// in seconds, MM:SS or HH:MM:SS, written as clip.poster.jpg
+    #[arg(long, value_name = "AT", num_args = 0..=1, default_missing_value = "scene", value_parser = PosterAt::parse)]
+    poster: Option<PosterAt>,
+
+    /// Tile N thumbnails spread over every video (default: 9) into clip.sheet.jpg
+    #[arg(long, value_name = "N", num_args = 0..=1, default_missing_value = "9", value_parser = clap::value_parser!(u32).range(1..=100))]
+    contact_sheet: Option<u32>,
+
+    /// Cut a short looping preview of every video, as a palette-optimized GIF (default) or an animated WebP
+    #[arg(long, value_name = "FORMAT", num_args = 0..=1, default_missing_value = "gif", value_parser = ["gif", "webp"])]
+    preview: Option<String>,
+
+    /// Encode every video to fit in SIZE (e.g. 8M, 500K), in two passes at the bitrate the
+    /// duration allows, instead of at a --level quality
+    #[arg(long, value_name = "SIZE", value_parser = TargetSize::parse, requires = "video_inputs")]
+    target_size: Option<TargetSize>,
+
+    /// Skip files whose output already exists and is newer than the source
+    #[arg(long)]
+    incremental: bool,
+
+    /// Continue an interrupted run, skipping files the manifest in the output directory marks as done
+    #[arg(long)]
+    resume: bool,
+
+    /// Write a machine-readable report. FILE ending in .ndjson/.jsonl streams one record per line, otherwise JSON
+    #[arg(long, value_name = "FILE")]
+    report: Option<PathBuf>,
+
+    /// ffmpeg binary to use instead of $CRUNCH_FFMPEG, the one on PATH or the embedded one
+    #[arg(long, value_name = "PATH")]
+    ffmpeg: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Manage the cached copy of the embedded ffmpeg
+    Cache {
+        #[command(subcommand)]
+        action: CacheAction,
+    },
 }
 
-fn process_images(ffmpeg: &Path, base_options: BaseCompressOptions) -> Result<()> {
+#[derive(Subcommand, Debug)]
+enum CacheAction {
+    /// Delete the cached ffmpeg binary and probe results, they are recreated on the next run
+    Clean,
+}
+
+/// Build the options for one media type (mt), or `None` when it wasn't requested.
+/// `--default` turns on every media type, using its default format unless one was given.
+fn base_options(
+    args: &Args,
+    mt: &str,
+    format: &Option<String>,
+    manifest: &Arc<Manifest>,
+) -> Option<BaseCompressOptions> {
+    if format.is_none() && !args.default {
+        return None;
+    }
+
+    let defaults = BaseCompressOptions::new_with(mt);
+    let never_grow = args.never_grow.as_deref().map(|action| match action {
+        "copy" => NoGainAction::Copy,
+        _ => NoGainAction::Skip,
+    });
+
+    Some(BaseCompressOptions {
+        input_path: args.input_path.clone(),
+        output_path: args.output_path.clone(),
+        output_extension: format.clone().unwrap_or(defaults.output_extension),
+        output_prefix: args.prefix.clone(),
+        level: args.level.clone(),
+        never_grow,
+        min_saving: args.min_saving,
+        incremental: args.incremental,
+        resize: Resize {
+            max_width: args.max_width,
+            max_height: args.max_height,
+            scale: args.scale,
+        },
+        metadata: match args.metadata.as_str() {
+            "strip" => MetadataPolicy::Strip,
+            "keep" => MetadataPolicy::Keep,
+            _ => MetadataPolicy::Safe,
+        },
+        manifest: Some(manifest.clone()),
+    })
+}
+
+fn process_images(
+    ffmpeg: &Path,
+    base_options: BaseCompressOptions,
+    responsive: Option<Responsive>,
+    animated: AnimatedOutput,
+) -> Result<Vec<FileRecord>> {
     let path = base_options.input_path.as_path();
     let images = get_image_files(path);
     let count = images.len();
 
     if count == 0 {
         println!("No images found to compress");
-        return Ok(());
+        return Ok(Vec::new());
     }
 
+    let formats = image_formats(&base_options.output_extension);
     println!(
         "Found {} images to compress to {}",
-        count, base_options.output_extension
+        count,
+        formats.join(", ")
     );
 
-    let options = ImageCompressOptions::with_base(base_options);
-    compress_all_images(ffmpeg, &images, &options)?;
+    // One set of options per format, each with the encoder settings of that format
+    let mut targets: Vec<ImageCompressOptions> = Vec::new();
+    for format in formats {
+        let mut options = ImageCompressOptions::with_base(BaseCompressOptions {
+            output_extension: format.clone(),
+            ..base_options.clone()
+        })?;
+        if let Some(other) = targets
+            .iter()
+            .find(|other| other.encoder.codec() == options.encoder.codec())
+        {
+            anyhow::bail!(
+                "--images lists {} and {}, which are the same format",
+                other.base.output_extension,
+                format
+            );
+        }
+        options.responsive = responsive.clone();
+        options.animated = animated.clone();
+        targets.push(options);
+    }
+
+    compress_all_images(ffmpeg, &images, &targets)
+}
 
-    println!("Successfully compressed {} images", count);
-    Ok(())
+/// The formats of `--images=avif,webp,jpg`, in the given order and without repeats.
+fn image_formats(formats: &str) -> Vec<String> {
+    let mut list: Vec<String> = Vec::new();
+    for format in formats
+        .split(',')
+        .map(|format| format.trim().to_lowercase())
+    {
+        if !list.contains(&format) {
+            list.push(format);
+        }
+    }
+    list
 }
 
-fn process_videos(ffmpeg: &Path, base_options: BaseCompressOptions) -> Result<()> {
+fn process_videos(
+    ffmpeg: &Path,
+    base_options: BaseCompressOptions,
+    target_size: Option<TargetSize>,
+) -> Result<Vec<FileRecord>> {
     let path = base_options.input_path.as_path();
     let videos = get_video_files(path);
     let count = videos.len();
 
     if count == 0 {
         println!("No videos found to compress");
-        return Ok(());
+        return Ok(Vec::new());
     }
 
     println!(
@@ -108,21 +323,34 @@ fn process_videos(ffmpeg: &Path, base_options: BaseCompressOptions) -> Result<()
         count, base_options.output_extension
     );
 
-    let options = VideoCompressOptions::with_base(base_options);
-    compress_all_videos(ffmpeg, &videos, &options)?;
+    let mut options = VideoCompressOptions::with_base(base_options)?;
+    options.target_size = target_size;
+    compress_all_videos(ffmpeg, &videos, &options)
+}
+
+fn process_previews(ffmpeg: &Path, options: PreviewOptions) -> Result<Vec<FileRecord>> {
+    let path = options.base.input_path.as_path();
+    let videos = get_video_files(path);
+    let count = videos.len();
 
-    println!("Successfully compressed {} videos", count);
-    Ok(())
+    if count == 0 {
+        println!("No videos found to extract previews from");
+        return Ok(Vec::new());
+    }
+
+    println!("Found {} videos to extract previews from", count);
+
+    extract_all_previews(ffmpeg, &videos, &options)
 }
 
-fn process_audios(ffmpeg: &Path, base_options: BaseCompressOptions) -> Result<()> {
+fn process_audios(ffmpeg: &Path, base_options: BaseCompressOptions) -> Result<Vec<FileRecord>> {
     let path = base_options.input_path.as_path();
     let audios = get_audio_files(path);
     let count = audios.len();
 
     if count == 0 {
         println!("No audios found to compress");
-        return Ok(());
+        return Ok(Vec::new());
     }
 
     println!(
@@ -130,17 +358,29 @@ fn process_audios(ffmpeg: &Path, base_options: BaseCompressOptions) -> Result<()
         count, base_options.output_extension
     );
 
-    let options = AudioCompressOptions::with_base(base_options);
-    compress_all_audios(ffmpeg, &audios, &options)?;
-
-    println!("Successfully compressed {} audios", count);
-    Ok(())
+    let options = AudioCompressOptions::with_base(base_options)?;
+    compress_all_audios(ffmpeg, &audios, &options)
 }
 
-fn main() -> Result<()> {
-    let ffmpeg = get_ffmpeg()?;
+fn main() -> Result<ExitCode> {
     let args = Args::parse();
 
+    if let Some(Command::Cache { action }) = &args.command {
+        match action {
+            CacheAction::Clean => {
+                let freed = clean_cache()?;
+                println!(
+                    "Removed {} ({} freed)",
+                    cache_dir()?.display(),
+                    format_size(freed)
+                );
+            }
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    install_cancel_handler()?;
+
     // Resolve the working path
     let path = args.input_path.as_path();
 
@@ -149,69 +389,180 @@ fn main() -> Result<()> {
     }
 
     // Determine what to process
-    let (is_process_videos, video_base_options) = if args.default {
-        (true, BaseCompressOptions::new_with("video"))
-    } else {
-        (
-            args.videos.is_some(),
-            BaseCompressOptions {
-                input_path: args.input_path.clone(),
-                output_path: args.output_path.clone(),
-                output_extension: args.videos.clone().unwrap_or_default(),
-                output_prefix: args.prefix.clone(),
-                level: args.level.clone(),
-            },
-        )
-    };
+    let previews = args.poster.is_some() || args.contact_sheet.is_some() || args.preview.is_some();
+    let requested = args.default
+        || args.videos.is_some()
+        || args.images.is_some()
+        || args.audios.is_some()
+        || previews;
+    if !requested {
+        println!("No conversion specified. Use --help for usage.");
+        return Ok(ExitCode::SUCCESS);
+    }
 
-    let (is_process_images, image_base_options) = if args.default {
-        (true, BaseCompressOptions::new_with("image"))
-    } else {
-        (
-            args.images.is_some(),
-            BaseCompressOptions {
-                input_path: args.input_path.clone(),
-                output_path: args.output_path.clone(),
-                output_extension: args.images.clone().unwrap_or_default(),
-                output_prefix: args.prefix.clone(),
-                level: args.level.clone(),
-            },
-        )
+    let ffmpeg = get_ffmpeg(args.ffmpeg.as_deref())?;
+    let capabilities = match Capabilities::load(&ffmpeg.path) {
+        Ok(capabilities) => {
+            let version = capabilities
+                .version
+                .as_deref()
+                .unwrap_or("(unknown version)");
+            println!(
+                "Using ffmpeg {} from {} ({})",
+                version,
+                ffmpeg.source.as_str(),
+                ffmpeg.path.display()
+            );
+            Some(capabilities)
+        }
+        Err(e) => {
+            eprintln!("WARNING: Could not probe ffmpeg capabilities: {}", e);
+            None
+        }
     };
 
-    let (is_process_audios, audio_base_options) = if args.default {
-        (true, BaseCompressOptions::new_with("audio"))
-    } else {
-        (
-            args.audios.is_some(),
-            BaseCompressOptions {
-                input_path: args.input_path.clone(),
-                output_path: args.output_path.clone(),
-                output_extension: args.audios.clone().unwrap_or_default(),
-                output_prefix: args.prefix.clone(),
-                level: args.level.clone(),
-            },
-        )
+    // Discards partial outputs of an interrupted run, and remembers what's done for --resume
+    let manifest = Arc::new(Manifest::open(&args.output_path, args.resume)?);
+
+    let video_base_options = base_options(&args, "video", &args.videos, &manifest);
+    let image_base_options = base_options(&args, "image", &args.images, &manifest);
+    let audio_base_options = base_options(&args, "audio", &args.audios, &manifest);
+    if args.target_size.is_some()
+        && let Some(base) = &video_base_options
+    {
+        let codec = VideoCompressOptions::with_base(base.clone())?.profile.codec;
+        if !codec.supports_two_pass() {
+            anyhow::bail!(
+                "--target-size needs a two-pass encode, which --videos={} ({}) can't run",
+                base.output_extension,
+                codec.encoder()
+            );
+        }
+    }
+    // Previews go next to the compressed videos, whether or not those are compressed too.
+    // Each extract has its own extension, the one given here is never used. An extract
+    // has no original to copy through, so --never-grow doesn't apply
+    let preview_options = previews
+        .then(|| base_options(&args, "video", &Some("jpg".to_string()), &manifest))
+        .flatten()
+        .map(|base| BaseCompressOptions {
+            never_grow: None,
+            ..base
+        })
+        .map(|base| PreviewOptions {
+            poster: args.poster,
+            contact_sheet: args.contact_sheet,
+            preview: args.preview.as_deref().map(|format| match format {
+                "webp" => PreviewFormat::WebP,
+                _ => PreviewFormat::Gif,
+            }),
+            base,
+        });
+
+    // Catch a format this ffmpeg can't write before anything is encoded
+    if let Some(capabilities) = &capabilities {
+        for (kind, base_options) in [
+            (MediaKind::Image, &image_base_options),
+            (MediaKind::Video, &video_base_options),
+            (MediaKind::Audio, &audio_base_options),
+        ] {
+            let Some(base_options) = base_options else {
+                continue;
+            };
+            let formats = match kind {
+                MediaKind::Image => image_formats(&base_options.output_extension),
+                _ => vec![base_options.output_extension.clone()],
+            };
+            for format in formats {
+                capabilities.check(kind, &format)?;
+            }
+            if kind == MediaKind::Image && args.animated != "image" {
+                capabilities.check(MediaKind::Video, &args.animated)?;
+            }
+        }
+        if let Some(options) = &preview_options {
+            if options.poster.is_some() || options.contact_sheet.is_some() {
+                capabilities.check(MediaKind::Preview, "jpg")?;
+            }
+            match options.preview {
+                Some(PreviewFormat::Gif) => capabilities.check(MediaKind::Preview, "gif")?,
+                Some(PreviewFormat::WebP) => capabilities.check(MediaKind::Preview, "webp")?,
+                None => {}
+            }
+        }
+    }
+
+    let mut report = match &args.report {
+        Some(path) => Some(Report::create(path)?),
+        None => None,
     };
 
-    // Check if anything to do
-    if !is_process_videos && !is_process_images && !is_process_audios {
-        println!("No conversion specified. Use --help for usage.");
-        return Ok(());
+    // Process media
+    let mut summary = Summary::new();
+
+    if let Some(image_base_options) = image_base_options
+        && !is_cancelled()
+    {
+        let responsive = args.responsive.clone().map(|widths| {
+            let srcset = args.srcset.as_deref().map(|format| match format {
+                "json" => SrcsetFormat::Json,
+                _ => SrcsetFormat::Html,
+            });
+            Responsive::new(widths, srcset)
+        });
+        let animated = AnimatedOutput::for_format(&args.animated)?;
+        let records = process_images(&ffmpeg.path, image_base_options, responsive, animated)?;
+        if let Some(report) = report.as_mut() {
+            report.write_batch(&records)?;
+        }
+        summary.extend(records);
     }
 
-    // Process media
-    if is_process_images {
-        process_images(&ffmpeg, image_base_options)?;
+    if let Some(video_base_options) = video_base_options
+        && !is_cancelled()
+    {
+        let records = process_videos(&ffmpeg.path, video_base_options, args.target_size)?;
+        if let Some(report) = report.as_mut() {
+            report.write_batch(&records)?;
+        }
+        summary.extend(records);
+    }
+
+    if let Some(preview_options) = preview_options
+        && !is_cancelled()
+    {
+        let records = process_previews(&ffmpeg.path, preview_options)?;
+        if let Some(report) = report.as_mut() {
+            report.write_batch(&records)?;
+        }
+        summary.extend(records);
+    }
+
+    if let Some(audio_base_options) = audio_base_options
+        && !is_cancelled()
+    {
+        let records = process_audios(&ffmpeg.path, audio_base_options)?;
+        if let Some(report) = report.as_mut() {
+            report.write_batch(&records)?;
+        }
+        summary.extend(records);
+    }
+
+    summary.print();
+
+    if let Some(report) = report {
+        report.finish(&summary)?;
     }
 
-    if is_process_videos {
-        process_videos(&ffmpeg, video_base_options)?;
+    if is_cancelled() {
+        println!("Cancelled, run again with --resume to continue");
+        return Ok(ExitCode::from(CANCELLED_EXIT_CODE));
     }
 
-    if is_process_audios {
-        process_audios(&ffmpeg, audio_base_options)?;
+    // Non-zero exit code so scripts notice partial failures
+    if summary.failures() > 0 {
+        return Ok(ExitCode::FAILURE);
     }
 
-    Ok(())
+    Ok(ExitCode::SUCCESS)
 }
@@ -0,0 +1,384 @@
+use crate::summary::{FileRecord, FileStatus};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Name of the manifest file, written to the root of the output directory.
+pub const MANIFEST_FILE: &str = ".crunch-manifest.jsonl";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryState {
+    /// ffmpeg was started, the output may be partial
+    Started,
+    /// Compressed, copied or skipped, nothing left to do
+    Done,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub state: EntryState,
+    pub output: Option<PathBuf>,
+    /// Settings the entry was produced with, so a resume with different settings redoes it
+    pub settings: BTreeMap<String, String>,
+}
+
+/// One line of the journal: where `input` stood with `codec` at that point.
+#[derive(Debug, Serialize, Deserialize)]
+struct JournalLine {
+    input: PathBuf,
+    codec: String,
+    #[serde(flatten)]
+    entry: ManifestEntry,
+}
+
+/// Keyed by input path, then by encoder, as an input can be compressed to several formats.
+type Entries = BTreeMap<PathBuf, BTreeMap<String, ManifestEntry>>;
+
+#[derive(Debug)]
+struct Journal {
+    entries: Entries,
+    file: File,
+}
+
+impl Journal {
+    /// Apply `line` to the entries and append it to the file.
+    fn record(&mut self, line: JournalLine) -> Result<()> {
+        let mut content = serde_json::to_vec(&line)?;
+        content.push(b'\n');
+        self.file.write_all(&content)?;
+
+        self.entries
+            .entry(line.input)
+            .or_default()
+            .insert(line.codec, line.entry);
+        Ok(())
+    }
+}
+
+/// Records the progress of a batch run so an interrupted run can be resumed.
+///
+/// The manifest is a journal: every start and finish appends one JSON line, the latest
+/// line for an input and encoder wins. It is compacted to one line per entry when it is
+/// opened and when the run ends, so it doesn't grow across runs.
+#[derive(Debug)]
+pub struct Manifest {
+    path: PathBuf,
+    journal: Mutex<Journal>,
+    resume: bool,
+}
+
+impl Manifest {
+    /// Open the manifest in `output_dir`.
+    ///
+    /// Outputs of files that were still being encoded when the previous run stopped are
+    /// deleted. The finished entries are kept either way, so `--incremental` can tell which
+    /// outputs are whose, but only with `resume` do they mark inputs as done.
+    /// A manifest that can't be read is reported and ignored.
+    pub fn open(output_dir: &Path, resume: bool) -> Result<Self> {
+        fs::create_dir_all(output_dir).context("Failed to create output directory")?;
+        let path = output_dir.join(MANIFEST_FILE);
+
+        let mut entries = read_journal(&path);
+        for entries in entries.values_mut() {
+            entries.retain(|_, entry| {
+                if entry.state == EntryState::Started
+                    && let Some(output) = &entry.output
+                {
+                    let _ = fs::remove_file(output);
+                }
+                entry.state != EntryState::Started
+            });
+        }
+        entries.retain(|_, entries| !entries.is_empty());
+
+        write_compacted(&path, &entries)?;
+        let file = OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open manifest: {}", path.display()))?;
+
+        Ok(Self {
+            path,
+            journal: Mutex::new(Journal { entries, file }),
+            resume,
+        })
+    }
+
+    /// Whether `input` was finished by a previous run with the same codec and settings.
+    /// Always false unless the run resumes.
+    pub fn is_done(&self, input: &Path, codec: &str, settings: &BTreeMap<String, String>) -> bool {
+        let journal = self.journal.lock().unwrap();
+        self.resume
+            && journal
+                .entries
+                .get(input)
+                .and_then(|entries| entries.get(codec))
+                .is_some_and(|entry| entry.state == EntryState::Done && &entry.settings == settings)
+    }
+
+    /// The output `input` was finished with `codec` to, by this run or an earlier one.
+    pub fn output(&self, input: &Path, codec: &str) -> Option<PathBuf> {
+        let journal = self.journal.lock().unwrap();
+        journal
+            .entries
+            .get(input)
+            .and_then(|entries| entries.get(codec))
+            .filter(|entry| entry.state == EntryState::Done)
+            .and_then(|entry| entry.output.clone())
+    }
+
+    /// Record that ffmpeg is about to write `output` for `input` with `codec`.
+    pub fn start(&self, input: &Path, codec: &str, output: &Path) -> Result<()> {
+        self.record(JournalLine {
+            input: input.to_path_buf(),
+            codec: codec.to_string(),
+            entry: ManifestEntry {
+                state: EntryState::Started,
+                output: Some(output.to_path_buf()),
+                settings: BTreeMap::new(),
+            },
+        })
+    }
+
+    /// Record how `record.input` ended up with `record.codec`. A skipped input keeps the
+    /// output of its previous entry, as e.g. an up to date input leaves it in place.
+    pub fn finish(&self, record: &FileRecord) -> Result<()> {
+        let state = match record.status {
+            FileStatus::Failed(_) => EntryState::Failed,
+            _ => EntryState::Done,
+        };
+        let output = match record.status {
+            FileStatus::Skipped(_) if record.output.is_none() => {
+                self.output(&record.input, &record.codec)
+            }
+            _ => record.output.clone(),
+        };
+
+        self.record(JournalLine {
+            input: record.input.clone(),
+            codec: record.codec.clone(),
+            entry: ManifestEntry {
+                state,
+                output,
+                settings: record.settings.clone(),
+            },
+        })
+    }
+
+    fn record(&self, line: JournalLine) -> Result<()> {
+        self.journal
+            .lock()
+            .unwrap()
+            .record(line)
+            .with_context(|| format!("Failed to write manifest: {}", self.path.display()))
+    }
+}
+
+impl Drop for Manifest {
+    fn drop(&mut self) {
+        let journal = self.journal.get_mut().unwrap();
+        if let Err(e) = write_compacted(&self.path, &journal.entries) {
+            eprintln!("WARNING: {}", e);
+        }
+    }
+}
+
+/// The entries of the journal at `path`. A line cut short by a crash, or a file that
+/// isn't a journal at all, is skipped with a warning.
+fn read_journal(path: &Path) -> Entries {
+    let mut entries = Entries::new();
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return entries,
+        Err(e) => {
+            eprintln!(
+                "WARNING: Ignoring unreadable manifest {}: {}",
+                path.display(),
+                e
+            );
+            return entries;
+        }
+    };
+
+    let mut unreadable = 0;
+    for line in content.lines().filter(|line| !line.trim().is_empty()) {
+        match serde_json::from_str::<JournalLine>(line) {
+            Ok(line) => {
+                entries
+                    .entry(line.input)
+                    .or_default()
+                    .insert(line.codec, line.entry);
+            }
+            Err(_) => unreadable += 1,
+        }
+    }
+    if unreadable > 0 {
+        eprintln!(
+            "WARNING: Ignoring {} unreadable lines of manifest {}",
+            unreadable,
+            path.display()
+        );
+    }
+
+    entries
+}
+
+/// Rewrite the journal at `path` with one line per entry (to a temporary file, then
+/// renamed into place, so it is never left half-written).
+fn write_compacted(path: &Path, entries: &Entries) -> Result<()> {
+    let mut content = Vec::new();
+    for (input, entries) in entries {
+        for (codec, entry) in entries {
+            let line = JournalLine {
+                input: input.clone(),
+                codec: codec.clone(),
+                entry: entry.clone(),
+            };
+            serde_json::to_writer(&mut content, &line)?;
+            content.push(b'\n');
+        }
+    }
+
+    let tmp = path.with_extension("jsonl.tmp");
+    fs::write(&tmp, content)
+        .and_then(|_| fs::rename(&tmp, path))
+        .with_context(|| format!("Failed to write manifest: {}", path.display()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::summary::MediaKind;
+    use tempfile::TempDir;
+
+    fn record(input: &Path, status: FileStatus) -> FileRecord {
+        FileRecord {
+            settings: BTreeMap::from([("crf".to_string(), "24".to_string())]),
+            ..FileRecord::for_test(MediaKind::Video, input, "libx264", status)
+        }
+    }
+
+    #[test]
+    fn test_manifest_resume() {
+        let temp_dir = TempDir::new().unwrap();
+        let out = temp_dir.path();
+        let settings = BTreeMap::from([("crf".to_string(), "24".to_string())]);
+
+        let done = Path::new("in/done.mp4");
+        let failed = Path::new("in/failed.mp4");
+        let partial = Path::new("in/partial.mp4");
+        let partial_output = out.join("partial.mp4");
+
+        let manifest = Manifest::open(out, false).unwrap();
+        manifest
+            .finish(&record(done, FileStatus::Compressed))
+            .unwrap();
+        manifest
+            .finish(&record(failed, FileStatus::Failed("boom".to_string())))
+            .unwrap();
+        manifest.start(partial, "libx264", &partial_output).unwrap();
+        fs::write(&partial_output, b"truncated").unwrap();
+        drop(manifest);
+
+        let manifest = Manifest::open(out, true).unwrap();
+        assert!(manifest.is_done(done, "libx264", &settings));
+        assert!(!manifest.is_done(failed, "libx264", &settings));
+        assert!(!manifest.is_done(partial, "libx264", &settings));
+        // The partial output of the interrupted encode is discarded
+        assert!(!partial_output.exists());
+        // Different settings have to be redone
+        assert!(!manifest.is_done(done, "libx265", &settings));
+        drop(manifest);
+
+        // Without resume the run starts over
+        let manifest = Manifest::open(out, false).unwrap();
+        assert!(!manifest.is_done(done, "libx264", &settings));
+    }
+
+    #[test]
+    fn test_manifest_entry_per_codec() {
+        let temp_dir = TempDir::new().unwrap();
+        let out = temp_dir.path();
+        let settings = BTreeMap::from([("crf".to_string(), "24".to_string())]);
+        let input = Path::new("in/photo.jpg");
+
+        let manifest = Manifest::open(out, true).unwrap();
+        manifest
+            .finish(&record(input, FileStatus::Compressed))
+            .unwrap();
+        manifest
+            .start(input, "libaom-av1", &out.join("photo.avif"))
+            .unwrap();
+        // The second format doesn't replace the first one's entry
+        assert!(manifest.is_done(input, "libx264", &settings));
+        assert!(!manifest.is_done(input, "libaom-av1", &settings));
+        drop(manifest);
+
+        // Compacted to the latest line per input and encoder
+        let content = fs::read_to_string(out.join(MANIFEST_FILE)).unwrap();
+        assert_eq!(content.lines().count(), 2);
+    }
+
+    #[test]
+    fn test_manifest_output() {
+        let temp_dir = TempDir::new().unwrap();
+        let out = temp_dir.path();
+        let input = Path::new("in/photo.jpg");
+        let copy = out.join("photo.jpg");
+
+        let manifest = Manifest::open(out, false).unwrap();
+        manifest
+            .finish(&FileRecord {
+                output: Some(copy.clone()),
+                ..record(input, FileStatus::Copied("no gain".to_string()))
+            })
+            .unwrap();
+        drop(manifest);
+
+        // Kept without resume, and through a run that found it up to date
+        let manifest = Manifest::open(out, false).unwrap();
+        assert_eq!(manifest.output(input, "libx264"), Some(copy.clone()));
+        manifest
+            .finish(&record(
+                input,
+                FileStatus::Skipped("up to date".to_string()),
+            ))
+            .unwrap();
+        assert_eq!(manifest.output(input, "libx264"), Some(copy));
+        assert_eq!(manifest.output(input, "libaom-av1"), None);
+    }
+
+    #[test]
+    fn test_manifest_unreadable() {
+        let temp_dir = TempDir::new().unwrap();
+        let out = temp_dir.path();
+        let settings = BTreeMap::from([("crf".to_string(), "24".to_string())]);
+        let done = Path::new("in/done.mp4");
+
+        let manifest = Manifest::open(out, false).unwrap();
+        manifest
+            .finish(&record(done, FileStatus::Compressed))
+            .unwrap();
+        drop(manifest);
+
+        // A line cut short by a crash is skipped, the lines before it still count
+        let path = out.join(MANIFEST_FILE);
+        let mut content = fs::read_to_string(&path).unwrap();
+        content.push_str(r#"{"input":"in/other.mp4","codec":"libx"#);
+        fs::write(&path, content).unwrap();
+        let manifest = Manifest::open(out, true).unwrap();
+        assert!(manifest.is_done(done, "libx264", &settings));
+        drop(manifest);
+
+        // Something that isn't a manifest at all starts over instead of failing the run
+        fs::write(&path, "not json").unwrap();
+        let manifest = Manifest::open(out, true).unwrap();
+        assert!(!manifest.is_done(done, "libx264", &settings));
+    }
+}
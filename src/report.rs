@@ -0,0 +1,285 @@
+use crate::ffmpeg::MediaInfo;
+use crate::summary::{FileRecord, FileStatus, Summary};
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    /// One JSON document with every record and the totals, written at the end of the run
+    Json,
+    /// One JSON record per line, written as each batch finishes
+    Ndjson,
+}
+
+impl ReportFormat {
+    /// `.ndjson` and `.jsonl` files get NDJSON, anything else a JSON document.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("ndjson") | Some("jsonl") => Self::Ndjson,
+            _ => Self::Json,
+        }
+    }
+}
+
+/// The machine-readable form of a [`FileRecord`].
+#[derive(Serialize)]
+struct ReportRecord<'a> {
+    input: &'a Path,
+    output: Option<&'a Path>,
+    /// Every image of a --responsive set
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    variants: &'a [PathBuf],
+    kind: &'static str,
+    codec: &'a str,
+    settings: &'a BTreeMap<String, String>,
+    input_size: u64,
+    output_size: Option<u64>,
+    elapsed_ms: u128,
+    status: &'static str,
+    /// Why a file was skipped, or copied through instead of re-encoded
+    reason: Option<&'a str>,
+    error: Option<&'a str>,
+    /// What probing the input found
+    media: Option<ReportMedia<'a>>,
+}
+
+/// The machine-readable form of a [`MediaInfo`].
+#[derive(Serialize)]
+struct ReportMedia<'a> {
+    duration_ms: Option<u128>,
+    bitrate: Option<u64>,
+    video_codec: Option<&'a str>,
+    width: Option<u32>,
+    height: Option<u32>,
+    fps: Option<f64>,
+    video_bitrate: Option<u64>,
+    audio_codec: Option<&'a str>,
+    channels: Option<u8>,
+    sample_rate: Option<u32>,
+    audio_bitrate: Option<u64>,
+}
+
+impl<'a> From<&'a MediaInfo> for ReportMedia<'a> {
+    fn from(info: &'a MediaInfo) -> Self {
+        Self {
+            duration_ms: info.duration.map(|duration| duration.as_millis()),
+            bitrate: info.bitrate,
+            video_codec: info.video_codec.as_deref(),
+            width: info.width,
+            height: info.height,
+            fps: info.fps,
+            video_bitrate: info.video_bitrate,
+            audio_codec: info.audio_codec.as_deref(),
+            channels: info.channels,
+            sample_rate: info.sample_rate,
+            audio_bitrate: info.audio_bitrate,
+        }
+    }
+}
+
+impl<'a> From<&'a FileRecord> for ReportRecord<'a> {
+    fn from(record: &'a FileRecord) -> Self {
+        Self {
+            input: &record.input,
+            output: record.output.as_deref(),
+            variants: &record.variants,
+            kind: record.kind.as_str(),
+            codec: &record.codec,
+            settings: &record.settings,
+            input_size: record.input_size,
+            output_size: record.output_size,
+            elapsed_ms: record.elapsed.as_millis(),
+            status: record.status.as_str(),
+            reason: match &record.status {
+                FileStatus::Skipped(reason) | FileStatus::Copied(reason) => Some(reason),
+                _ => None,
+            },
+            error: record.error(),
+            media: record.media.as_ref().map(ReportMedia::from),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ReportTotals {
+    files: usize,
+    failed: usize,
+    input_size: u64,
+    output_size: u64,
+    saved_bytes: i64,
+}
+
+#[derive(Serialize)]
+struct ReportDocument<'a> {
+    records: Vec<ReportRecord<'a>>,
+    totals: ReportTotals,
+}
+
+/// Writes the `--report` file.
+pub struct Report {
+    path: PathBuf,
+    format: ReportFormat,
+    writer: BufWriter<File>,
+}
+
+impl Report {
+    /// Create (or truncate) the report file up front, so a bad path fails before any encoding.
+    pub fn create(path: &Path) -> Result<Self> {
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create report file: {}", path.display()))?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            format: ReportFormat::from_path(path),
+            writer: BufWriter::new(file),
+        })
+    }
+
+    /// Stream a finished batch. Only NDJSON reports are written incrementally.
+    pub fn write_batch(&mut self, records: &[FileRecord]) -> Result<()> {
+        if self.format != ReportFormat::Ndjson {
+            return Ok(());
+        }
+
+        for record in records {
+            serde_json::to_writer(&mut self.writer, &ReportRecord::from(record))?;
+            self.writer.write_all(b"\n")?;
+        }
+        self.writer.flush()?;
+
+        Ok(())
+    }
+
+    /// Write the JSON document, or just flush an NDJSON stream.
+    pub fn finish(mut self, summary: &Summary) -> Result<()> {
+        if self.format == ReportFormat::Json {
+            let (input_size, output_size) = summary.totals();
+            let document = ReportDocument {
+                records: summary.records.iter().map(ReportRecord::from).collect(),
+                totals: ReportTotals {
+                    files: summary.records.len(),
+                    failed: summary.failures(),
+                    input_size,
+                    output_size,
+                    saved_bytes: input_size as i64 - output_size as i64,
+                },
+            };
+            serde_json::to_writer_pretty(&mut self.writer, &document)?;
+            self.writer.write_all(b"\n")?;
+        }
+
+        self.writer
+            .flush()
+            .with_context(|| format!("Failed to write report file: {}", self.path.display()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::summary::MediaKind;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    fn record(error: Option<&str>) -> FileRecord {
+        let status = match error {
+            Some(error) => FileStatus::Failed(error.to_string()),
+            None => FileStatus::Compressed,
+        };
+        FileRecord {
+            output: error.is_none().then(|| PathBuf::from("out/a.mp3")),
+            settings: BTreeMap::from([("bitrate".to_string(), "128k".to_string())]),
+            input_size: 1000,
+            output_size: error.is_none().then_some(100),
+            elapsed: Duration::from_millis(1500),
+            ..FileRecord::for_test(
+                MediaKind::Audio,
+                Path::new("in/a.wav"),
+                "libmp3lame",
+                status,
+            )
+        }
+    }
+
+    #[test]
+    fn test_report_format_from_path() {
+        assert_eq!(
+            ReportFormat::from_path(Path::new("r.json")),
+            ReportFormat::Json
+        );
+        assert_eq!(
+            ReportFormat::from_path(Path::new("r.ndjson")),
+            ReportFormat::Ndjson
+        );
+        assert_eq!(
+            ReportFormat::from_path(Path::new("r.jsonl")),
+            ReportFormat::Ndjson
+        );
+        assert_eq!(
+            ReportFormat::from_path(Path::new("report")),
+            ReportFormat::Json
+        );
+    }
+
+    #[test]
+    fn test_ndjson_report() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("report.ndjson");
+        let mut compressed = record(None);
+        compressed.media = Some(MediaInfo {
+            duration: Some(Duration::from_millis(2500)),
+            audio_codec: Some("pcm_s16le".to_string()),
+            sample_rate: Some(44100),
+            ..MediaInfo::default()
+        });
+        let copied = FileRecord {
+            status: FileStatus::Copied("already optimal".to_string()),
+            ..record(None)
+        };
+        let records = vec![compressed, record(Some("Invalid data")), copied];
+
+        let mut report = Report::create(&path).unwrap();
+        report.write_batch(&records).unwrap();
+        report.finish(&Summary::default()).unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<serde_json::Value> = content
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0]["status"], "compressed");
+        assert_eq!(lines[0]["settings"]["bitrate"], "128k");
+        assert_eq!(lines[0]["media"]["duration_ms"], 2500);
+        assert_eq!(lines[0]["media"]["sample_rate"], 44100);
+        assert_eq!(lines[1]["status"], "failed");
+        assert_eq!(lines[1]["error"], "Invalid data");
+        assert!(lines[1]["media"].is_null());
+        assert_eq!(lines[2]["status"], "copied");
+        assert_eq!(lines[2]["reason"], "already optimal");
+    }
+
+    #[test]
+    fn test_json_report() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("report.json");
+        let mut summary = Summary::new();
+        summary.extend(vec![record(None), record(Some("Invalid data"))]);
+
+        let mut report = Report::create(&path).unwrap();
+        report.write_batch(&summary.records).unwrap();
+        report.finish(&summary).unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let document: serde_json::Value = serde_json::from_str(&content).unwrap();
+
+        assert_eq!(document["records"].as_array().unwrap().len(), 2);
+        assert_eq!(document["totals"]["failed"], 1);
+        assert_eq!(document["totals"]["saved_bytes"], 900);
+    }
+}
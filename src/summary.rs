@@ -0,0 +1,434 @@
+use crate::ffmpeg::{MediaInfo, Outcome};
+use anyhow::Result;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaKind {
+    Image,
+    Video,
+    Audio,
+    /// A poster, contact sheet or animated preview extracted from a video
+    Preview,
+}
+
+impl MediaKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Image => "image",
+            Self::Video => "video",
+            Self::Audio => "audio",
+            Self::Preview => "preview",
+        }
+    }
+}
+
+/// How a single input file ended up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileStatus {
+    Compressed,
+    /// The original was copied through instead of re-encoded, with the reason, e.g.
+    /// "no gain" or "already optimal"
+    Copied(String),
+    /// Nothing was written, with the reason
+    Skipped(String),
+    /// Compression failed, with the error (including ffmpeg's stderr)
+    Failed(String),
+}
+
+impl FileStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Compressed => "compressed",
+            Self::Copied(_) => "copied",
+            Self::Skipped(_) => "skipped",
+            Self::Failed(_) => "failed",
+        }
+    }
+}
+
+/// What happened to a single input file.
+#[derive(Debug)]
+pub struct FileRecord {
+    pub kind: MediaKind,
+    pub input: PathBuf,
+    pub output: Option<PathBuf>,
+    /// Every image of a `--responsive` set, empty otherwise
+    pub variants: Vec<PathBuf>,
+    /// ffmpeg encoder, e.g. "libwebp"
+    pub codec: String,
+    /// Encoder settings derived from --level, e.g. "crf" => "33"
+    pub settings: BTreeMap<String, String>,
+    pub input_size: u64,
+    pub output_size: Option<u64>,
+    pub elapsed: Duration,
+    /// What probing the input found, `None` when it wasn't probed
+    pub media: Option<MediaInfo>,
+    pub status: FileStatus,
+}
+
+impl FileRecord {
+    /// Build a record from the result of one `compress_*` call.
+    pub fn new(kind: MediaKind, input: &Path, result: &Result<Outcome>, elapsed: Duration) -> Self {
+        let input_size = file_size(input).unwrap_or(0);
+
+        let mut variants = Vec::new();
+        let (output, status) = match result {
+            Ok(Outcome::Compressed(output)) => (Some(output.clone()), FileStatus::Compressed),
+            // The largest image stands for the set
+            Ok(Outcome::CompressedSet(outputs)) => {
+                variants = outputs.clone();
+                (outputs.last().cloned(), FileStatus::Compressed)
+            }
+            Ok(Outcome::Copied(output, reason)) => {
+                (Some(output.clone()), FileStatus::Copied(reason.clone()))
+            }
+            Ok(Outcome::Skipped(reason)) => (None, FileStatus::Skipped(reason.clone())),
+            Err(e) => (None, FileStatus::Failed(e.to_string())),
+        };
+        // A set costs all of its images
+        let output_size = if variants.is_empty() {
+            output.as_deref().and_then(file_size)
+        } else {
+            variants.iter().map(|path| file_size(path)).sum()
+        };
+
+        Self {
+            kind,
+            input: input.to_path_buf(),
+            output,
+            variants,
+            codec: String::new(),
+            settings: BTreeMap::new(),
+            input_size,
+            output_size,
+            elapsed,
+            media: None,
+            status,
+        }
+    }
+
+    /// Attach the encoder and settings the file was compressed with.
+    pub fn with_encoder(mut self, codec: &str, settings: BTreeMap<String, String>) -> Self {
+        self.codec = codec.to_string();
+        self.settings = settings;
+        self
+    }
+
+    pub fn with_media(mut self, media: Option<MediaInfo>) -> Self {
+        self.media = media;
+        self
+    }
+
+    pub fn is_failed(&self) -> bool {
+        matches!(self.status, FileStatus::Failed(_))
+    }
+
+    pub fn error(&self) -> Option<&str> {
+        match &self.status {
+            FileStatus::Failed(error) => Some(error),
+            _ => None,
+        }
+    }
+
+    /// Bytes saved by this file, negative when the output grew.
+    pub fn saved_bytes(&self) -> i64 {
+        match self.output_size {
+            Some(size) => self.input_size as i64 - size as i64,
+            None => 0,
+        }
+    }
+
+    /// Output size as a fraction of the input size.
+    pub fn ratio(&self) -> Option<f64> {
+        match self.output_size {
+            Some(size) if self.input_size > 0 => Some(size as f64 / self.input_size as f64),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+impl FileRecord {
+    /// A record with just what tests usually care about, the rest is filled in
+    /// with struct update syntax.
+    pub fn for_test(kind: MediaKind, input: &Path, codec: &str, status: FileStatus) -> Self {
+        Self {
+            kind,
+            input: input.to_path_buf(),
+            output: None,
+            variants: Vec::new(),
+            codec: codec.to_string(),
+            settings: BTreeMap::new(),
+            input_size: 0,
+            output_size: None,
+            elapsed: Duration::ZERO,
+            media: None,
+            status,
+        }
+    }
+}
+
+fn file_size(path: &Path) -> Option<u64> {
+    fs::metadata(path).ok().map(|m| m.len())
+}
+
+/// Collects the records of every batch in a run and prints the end-of-run report.
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub records: Vec<FileRecord>,
+}
+
+impl Summary {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn extend(&mut self, records: Vec<FileRecord>) {
+        self.records.extend(records);
+    }
+
+    pub fn failures(&self) -> usize {
+        self.records.iter().filter(|r| r.is_failed()).count()
+    }
+
+    /// Number of records with the same status variant as `status`.
+    pub fn count(&self, status: &FileStatus) -> usize {
+        self.records
+            .iter()
+            .filter(|r| r.status.as_str() == status.as_str())
+            .count()
+    }
+
+    /// Total input size and output size of the files that were compressed.
+    /// Previews are extra files rather than smaller versions, so they don't count.
+    pub fn totals(&self) -> (u64, u64) {
+        self.records
+            .iter()
+            .filter(|r| r.kind != MediaKind::Preview)
+            .filter_map(|r| r.output_size.map(|size| (r.input_size, size)))
+            .fold((0, 0), |(input, output), (i, o)| (input + i, output + o))
+    }
+
+    /// Print a table of every file, largest savings first and failures last, then the totals.
+    pub fn print(&self) {
+        if self.records.is_empty() {
+            return;
+        }
+
+        let mut records: Vec<&FileRecord> = self.records.iter().collect();
+        records.sort_by(|a, b| {
+            a.is_failed()
+                .cmp(&b.is_failed())
+                .then(b.saved_bytes().cmp(&a.saved_bytes()))
+        });
+
+        println!();
+        println!(
+            "{:<40} {:<7} {:>10} {:>10} {:>7} {:>8}",
+            "File", "Kind", "Original", "Output", "Ratio", "Time"
+        );
+        // An input compressed to several formats has a row per format
+        let mut rows_per_input: HashMap<&Path, usize> = HashMap::new();
+        for record in &records {
+            *rows_per_input.entry(&record.input).or_default() += 1;
+        }
+
+        println!("{}", "-".repeat(87));
+
+        for record in &records {
+            let mut name = record
+                .input
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned();
+            if rows_per_input[record.input.as_path()] > 1 {
+                name = format!("{} [{}]", name, record.codec);
+            }
+            let output = match (&record.status, record.output_size) {
+                (FileStatus::Skipped(_), _) => "SKIPPED".to_string(),
+                (FileStatus::Failed(_), _) | (_, None) => "FAILED".to_string(),
+                (_, Some(size)) => format_size(size),
+            };
+            let ratio = match record.ratio() {
+                Some(ratio) => format!("{:.0}%", ratio * 100.0),
+                None => "-".to_string(),
+            };
+
+            println!(
+                "{:<40} {:<7} {:>10} {:>10} {:>7} {:>8.1?}",
+                truncate(&name, 40),
+                record.kind.as_str(),
+                format_size(record.input_size),
+                output,
+                ratio,
+                record.elapsed
+            );
+        }
+
+        println!("{}", "-".repeat(87));
+
+        let (input, output) = self.totals();
+        let saved = input as i64 - output as i64;
+        let reduction = if input > 0 {
+            saved as f64 / input as f64 * 100.0
+        } else {
+            0.0
+        };
+
+        println!(
+            "Total: {} -> {}, saved {}{} ({:.1}%), {} compressed, {} copied, {} skipped, {} failed",
+            format_size(input),
+            format_size(output),
+            if saved < 0 { "-" } else { "" },
+            format_size(saved.unsigned_abs()),
+            reduction,
+            self.count(&FileStatus::Compressed),
+            self.count(&FileStatus::Copied(String::new())),
+            self.count(&FileStatus::Skipped(String::new())),
+            self.failures()
+        );
+
+        for record in &records {
+            if let FileStatus::Copied(reason) = &record.status {
+                println!("COPIED: {} ({})", record.input.display(), reason);
+            }
+        }
+
+        for record in records.iter().filter(|r| r.is_failed()) {
+            // ffmpeg prints the actual error last, after the input info
+            let reason = record.error().unwrap_or_default();
+            let reason = reason
+                .lines()
+                .rev()
+                .find(|line| !line.trim().is_empty())
+                .unwrap_or_default();
+            println!("FAILED: {} ({})", record.input.display(), reason.trim());
+        }
+    }
+}
+
+/// Format a byte count with a binary unit, e.g. 1536 -> "1.5 KB".
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["B", "KB", "MB", "GB", "TB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+fn truncate(name: &str, width: usize) -> String {
+    if name.chars().count() <= width {
+        return name.to_string();
+    }
+
+    let kept: String = name.chars().take(width - 3).collect();
+    format!("{}...", kept)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn record(input_size: u64, output_size: Option<u64>) -> FileRecord {
+        let status = match output_size {
+            Some(_) => FileStatus::Compressed,
+            None => FileStatus::Failed("boom".to_string()),
+        };
+        FileRecord {
+            output: output_size.map(|_| PathBuf::from("a.webp")),
+            input_size,
+            output_size,
+            elapsed: Duration::from_secs(1),
+            ..FileRecord::for_test(MediaKind::Image, Path::new("a.jpg"), "libwebp", status)
+        }
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(0), "0 B");
+        assert_eq!(format_size(1023), "1023 B");
+        assert_eq!(format_size(1536), "1.5 KB");
+        assert_eq!(format_size(5 * 1024 * 1024), "5.0 MB");
+    }
+
+    #[test]
+    fn test_summary_totals() {
+        let mut summary = Summary::new();
+        summary.extend(vec![
+            record(1000, Some(400)),
+            record(500, Some(600)),
+            record(800, None),
+        ]);
+
+        assert_eq!(summary.failures(), 1);
+        // Failed files don't count towards the totals
+        assert_eq!(summary.totals(), (1500, 1000));
+        assert_eq!(summary.records[0].saved_bytes(), 600);
+        assert_eq!(summary.records[1].saved_bytes(), -100);
+        assert_eq!(summary.records[0].ratio(), Some(0.4));
+    }
+
+    #[test]
+    fn test_file_record_from_error() {
+        let result: Result<Outcome> = Err(anyhow::anyhow!("ffmpeg failed"));
+        let record = FileRecord::new(
+            MediaKind::Video,
+            Path::new("/nonexistent/a.mp4"),
+            &result,
+            Duration::ZERO,
+        );
+
+        assert!(record.is_failed());
+        assert_eq!(record.input_size, 0);
+        assert_eq!(record.error(), Some("ffmpeg failed"));
+    }
+
+    #[test]
+    fn test_file_record_skipped() {
+        let result = Ok(Outcome::Skipped("no gain".to_string()));
+        let record = FileRecord::new(
+            MediaKind::Image,
+            Path::new("/nonexistent/a.jpg"),
+            &result,
+            Duration::ZERO,
+        );
+
+        assert!(!record.is_failed());
+        assert_eq!(record.status, FileStatus::Skipped("no gain".to_string()));
+        assert_eq!(record.output_size, None);
+        assert_eq!(record.saved_bytes(), 0);
+    }
+
+    #[test]
+    fn test_file_record_copied() {
+        let result = Ok(Outcome::Copied(
+            PathBuf::from("/nonexistent/out/a.mp4"),
+            "already optimal".to_string(),
+        ));
+        let record = FileRecord::new(
+            MediaKind::Video,
+            Path::new("/nonexistent/a.mkv"),
+            &result,
+            Duration::ZERO,
+        );
+
+        assert_eq!(
+            record.status,
+            FileStatus::Copied("already optimal".to_string())
+        );
+        assert_eq!(record.output, Some(PathBuf::from("/nonexistent/out/a.mp4")));
+    }
+}